@@ -38,18 +38,21 @@ self.onmessage = async function(e) {
             error: 'WASM not yet loaded in worker. Please use main thread computation for now.'
         }));
         
-        // TODO: Once WASM is loaded in worker, uncomment this:
+        // TODO: Once WASM is loaded in worker, uncomment this. The bindings
+        // come from spectre-rs built with its `wasm` feature (src/wasm.rs).
         /*
         await initWasm();
         if (wasmModule && wasmReady) {
             try {
-                const key = wasmModule.spectre_user_key(data.name, data.secret);
+                const key = wasmModule.spectre_user_key(data.name, data.secret, data.algorithm, data.normalization);
                 const keyResult = {
                     type: 'key_result',
-                    key_id: Array.from(key.key_id),
-                    key_data: Array.from(key.key_data),
-                    algorithm: key.algorithm
+                    key_id: Array.from(key.keyId),
+                    key_data: Array.from(key.keyData),
+                    algorithm: key.algorithm,
+                    normalization: key.normalization
                 };
+                key.free();
                 self.postMessage(JSON.stringify(keyResult));
             } catch (err) {
                 self.postMessage(JSON.stringify({
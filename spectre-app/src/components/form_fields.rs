@@ -67,7 +67,9 @@ pub fn SpectreSecretInput(
 #[component]
 pub fn SiteDomainInput(
     site_domain: Signal<String>,
+    canonical_site: Memo<String>,
     is_computing_key: Signal<bool>,
+    key_progress: Signal<f32>,
     on_focus: EventHandler<()>,
 ) -> Element {
     rsx! {
@@ -76,6 +78,15 @@ pub fn SiteDomainInput(
                 class: "block text-xs tracking-wider text-slate-500 mb-2",
                 "Site Domain"
             }
+            if is_computing_key() {
+                div {
+                    class: "h-0.5 bg-slate-800 rounded-full overflow-hidden mb-2",
+                    div {
+                        class: "h-full bg-cyan-500 transition-all",
+                        style: "width: {key_progress() * 100.0}%",
+                    }
+                }
+            }
             input {
                 class: "w-full bg-slate-800/50 border border-slate-700 rounded-xl px-4 py-2 text-white placeholder-slate-500 focus:outline-none focus:ring-2 focus:ring-cyan-500/50 focus:border-cyan-500/50 transition-all",
                 r#type: "text",
@@ -84,6 +95,13 @@ pub fn SiteDomainInput(
                 oninput: move |evt| site_domain.set(evt.value()),
                 onfocus: move |_| on_focus.call(()),
             }
+            if canonical_site() != site_domain().trim() {
+                p {
+                    class: "text-xs text-slate-500 mt-1",
+                    "Using "
+                    span { class: "text-cyan-400", "{canonical_site}" }
+                }
+            }
         }
     }
 }
@@ -1,18 +1,22 @@
 use dioxus::prelude::*;
+use spectre::SpectreIdenticon;
 
 #[component]
-pub fn IdenticonDisplay(identicon: Signal<String>) -> Element {
+pub fn IdenticonDisplay(identicon: Signal<Option<SpectreIdenticon>>) -> Element {
     rsx! {
-        if !identicon.read().is_empty() {
+        if let Some(identicon) = *identicon.read() {
             div {
                 class: "flex items-center justify-center gap-2 py-1 text-slate-400 text-sm",
                 "Your Identity:"
                 div {
-                    class: "flex items-center gap-1 bg-slate-800/50 px-3 py-1 rounded-lg border border-slate-700 text-xl",
-                    "{identicon}"
+                    class: "flex items-center gap-1 bg-slate-800/50 px-3 py-1 rounded-lg border border-slate-700 text-xl font-mono",
+                    title: "Check that this matches the identicon on your other Spectre clients",
+                    span {
+                        style: "color: {identicon.color.css()}",
+                        "{identicon}"
+                    }
                 }
             }
         }
     }
 }
-
@@ -1,8 +1,13 @@
 use dioxus::prelude::*;
-use spectre::SpectreResultType;
+use spectre::{SpectreResultType, SpectreStrength, SPECTRE_ENTROPY_WARNING_BITS};
 
 #[component]
-pub fn PasswordTypeSelector(password_type: Signal<SpectreResultType>) -> Element {
+pub fn PasswordTypeSelector(
+    password_type: Signal<SpectreResultType>,
+    /// Types with fewer bits of entropy than this get a warning
+    #[props(default = SPECTRE_ENTROPY_WARNING_BITS)]
+    entropy_warning: f64,
+) -> Element {
     rsx! {
         section {
             class: "mb-3",
@@ -52,12 +57,43 @@ pub fn PasswordTypeSelector(password_type: Signal<SpectreResultType>) -> Element
                     result_type: SpectreResultType::Phrase,
                     label: "Phrase"
                 }
+                PasswordTypeButton {
+                    password_type: password_type,
+                    result_type: SpectreResultType::Mnemonic,
+                    label: "Words"
+                }
                 PasswordTypeButton {
                     password_type: password_type,
                     result_type: SpectreResultType::MaximumSecurityPassword,
                     label: "Maximum"
                 }
             }
+            
+            EntropyInfo { password_type, entropy_warning }
+        }
+    }
+}
+
+#[component]
+fn EntropyInfo(password_type: Signal<SpectreResultType>, entropy_warning: f64) -> Element {
+    let Some(bits) = password_type.read().entropy() else {
+        return rsx! {};
+    };
+    let strength = SpectreStrength::from_entropy(bits);
+    
+    rsx! {
+        div {
+            class: "flex items-center justify-between mt-2 text-xs",
+            span {
+                class: "text-slate-500",
+                "{bits:.1} bits of entropy · {strength.name()}"
+            }
+            if bits < entropy_warning {
+                span {
+                    class: "text-amber-400",
+                    "Only use where guesses are rate-limited"
+                }
+            }
         }
     }
 }
@@ -1,6 +1,7 @@
 use dioxus::prelude::*;
-use spectre::{spectre_user_key, spectre_site_result, spectre_identicon_render, spectre_identicon_preview};
-use spectre::{SpectreResultType, SpectreKeyPurpose, SPECTRE_ALGORITHM_CURRENT, SpectreUserKey, SPECTRE_COUNTER_DEFAULT};
+use spectre::{spectre_site_result, spectre_site_name, spectre_identicon};
+use spectre::{SpectreResultType, SpectreKeyPurpose, SPECTRE_ALGORITHM_CURRENT, SpectreIdenticon, SpectreUserKey, SPECTRE_COUNTER_DEFAULT};
+use spectre::{SpectreCancelToken, SpectreNormalization, SpectreUserKeyDerivation, SPECTRE_DERIVATION_STEP};
 use gloo_timers::future::sleep;
 use std::time::Duration;
 
@@ -41,16 +42,22 @@ fn Home() -> Element {
     let site_domain = use_signal(|| String::new());
     let password_type = use_signal(|| SpectreResultType::LongPassword);
     let counter = use_signal(|| SPECTRE_COUNTER_DEFAULT);
+    // There's no user file to read a normalization from, so keys are derived as new users' are
+    let normalization = SpectreNormalization::default();
     let mut generated_password = use_signal(|| String::new());
-    let mut identicon = use_signal(|| String::new());
+    let mut identicon = use_signal(|| Option::<SpectreIdenticon>::None);
     let mut is_generating = use_signal(|| false);
     let mut is_computing_key = use_signal(|| false);
+    let key_progress = use_signal(|| 0.0f32);
     let mut trigger_password_generation = use_signal(|| 0u32);
     
     // Cache the user key to avoid recomputing scrypt for every site
     // The expensive operation (scrypt) only runs when name or secret changes
     let mut cached_user_key = use_signal(|| Option::<(String, String, SpectreUserKey)>::None);
     
+    // The in-flight main-thread derivation, so it can be abandoned once it's stale
+    let mut key_cancel = use_signal(|| Option::<SpectreCancelToken>::None);
+    
     // Initialize Web Worker for background key generation
     // Falls back to main thread if worker initialization fails
     // Use Arc to share the worker across async contexts
@@ -68,6 +75,11 @@ fn Home() -> Element {
         let sec = secret.read();
         !sec.is_empty() && sec.len() >= 4
     });
+    // The registrable domain of what was typed or pasted; free-form names are kept as they are
+    let canonical_site = use_memo(move || {
+        let site = site_domain();
+        spectre_site_name(&site).unwrap_or(site)
+    });
     let _domain_valid = use_memo(move || {
         let site = site_domain.read();
         !site.is_empty() && site.len() >= 3
@@ -76,8 +88,7 @@ fn Home() -> Element {
     // Track when to trigger key computation
     let mut trigger_key_computation = use_signal(|| 0u32);
     
-    // Preview identicon - shows immediately as user types (uses fast SHA256)
-    // This gives instant visual feedback before the expensive scrypt computation
+    // Identicon - shows immediately as user types (HMAC-SHA256, no scrypt involved)
     use_effect(move || {
         let name = full_name();
         let sec = secret();
@@ -87,11 +98,18 @@ fn Home() -> Element {
         let secret_is_valid = !sec.is_empty() && sec.len() >= 4;
         
         if name_is_valid && secret_is_valid {
-            // Generate fast preview identicon (SHA256 - instant)
-            let preview_bytes = spectre_identicon_preview(&name, &sec);
-            identicon.set(spectre_identicon_render(preview_bytes));
+            identicon.set(Some(spectre_identicon(&name, &sec)));
         } else {
-            identicon.set(String::new());
+            identicon.set(None);
+        }
+    });
+    
+    // Abort an in-flight derivation as soon as the name or secret it was started for changes
+    use_effect(move || {
+        let _ = (full_name(), secret());
+        if let Some(cancel) = key_cancel.take() {
+            cancel.cancel();
+            is_computing_key.set(false);
         }
     });
     
@@ -129,44 +147,38 @@ fn Home() -> Element {
                 };
                 
                 if needs_computation {
-                    // Keep preview identicon visible while computing real key
                     // Show that we're computing the key
                     is_computing_key.set(true);
                     
                     // Small delay to let the UI update
                     sleep(Duration::from_millis(50)).await;
                     
+                    // Supersede any derivation that is still running
+                    let cancel = SpectreCancelToken::new();
+                    if let Some(previous) = key_cancel.replace(Some(cancel.clone())) {
+                        previous.cancel();
+                    }
+                    
                     // Try to use Web Worker for background computation, fallback to main thread
-                    let key_result = if let Some(ref worker) = worker_ref {
-                        // Use Web Worker (runs in background thread)
-                        // If worker fails, fallback to main thread
-                        worker.generate_key(name.clone(), sec.clone()).await
-                            .or_else(|_| {
-                                // Worker failed, fallback to main thread
-                                spectre_user_key(&name, &sec, SPECTRE_ALGORITHM_CURRENT)
-                                    .map_err(|e| format!("Key generation failed: {:?}", e))
-                            })
-                    } else {
-                        // Fallback to main thread if worker not available
-                        spectre_user_key(&name, &sec, SPECTRE_ALGORITHM_CURRENT)
-                            .map_err(|e| format!("Key generation failed: {:?}", e))
+                    let worker_result = match worker_ref {
+                        Some(ref worker) => worker.generate_key(name.clone(), sec.clone(), normalization).await,
+                        None => Err("Worker not available".to_string()),
+                    };
+                    let key_result = match worker_result {
+                        Ok(key) => Ok(key),
+                        Err(_) => derive_user_key(&name, &sec, normalization, &cancel, key_progress).await,
                     };
                     
-                    if let Ok(key) = key_result {
-                        cached_user_key.set(Some((name.clone(), sec.clone(), key.clone())));
+                    // A cancelled derivation has been superseded; leave the state to its successor
+                    if !cancel.is_cancelled() {
+                        if let Ok(key) = key_result {
+                            cached_user_key.set(Some((name.clone(), sec.clone(), key)));
+                        }
                         
-                        // Generate identicon immediately after key is computed
-                        let mut identicon_bytes = [0u8; 4];
-                        identicon_bytes.copy_from_slice(&key.key_data[0..4]);
-                        identicon.set(spectre_identicon_render(identicon_bytes));
+                        // Always reset the computing state, even on error
+                        is_computing_key.set(false);
                     }
-                    
-                    // Always reset the computing state, even on error
-                    is_computing_key.set(false);
                 }
-            } else {
-                // Clear identicon if inputs are invalid
-                identicon.set(String::new());
             }
         });
     });
@@ -181,7 +193,7 @@ fn Home() -> Element {
         // Clone the values we need
         let name = full_name();
         let sec = secret();
-        let site = site_domain();
+        let site = canonical_site();
         let result_type = password_type();
         let counter_value = counter();
         let worker_ref = (*key_worker.read()).clone(); // Clone the Arc reference
@@ -191,6 +203,11 @@ fn Home() -> Element {
             // Wait for 500ms before generating the password (shorter now since key is precomputed)
             sleep(Duration::from_millis(500)).await;
             
+            // The user kept typing; a newer task will handle the current input
+            if *full_name.peek() != name || *secret.peek() != sec {
+                return;
+            }
+            
             // Validate fields
             let name_is_valid = !name.is_empty() && name.len() >= 3;
             let secret_is_valid = !sec.is_empty() && sec.len() >= 4;
@@ -222,26 +239,27 @@ fn Home() -> Element {
                     // Small delay to show loading state
                     sleep(Duration::from_millis(50)).await;
                     
+                    // Supersede any derivation that is still running
+                    let cancel = SpectreCancelToken::new();
+                    if let Some(previous) = key_cancel.replace(Some(cancel.clone())) {
+                        previous.cancel();
+                    }
+                    
                     // Try to use Web Worker for background computation, fallback to main thread
                     let result = if let Some(ref worker) = worker_ref {
                         // Use Web Worker (runs in background thread)
-                        worker.generate_key(name.clone(), sec.clone()).await
+                        worker.generate_key(name.clone(), sec.clone(), normalization).await
                     } else {
                         // Fallback to main thread if worker not available
-                        spectre_user_key(&name, &sec, SPECTRE_ALGORITHM_CURRENT)
-                            .map_err(|e| format!("Key generation failed: {:?}", e))
+                        derive_user_key(&name, &sec, normalization, &cancel, key_progress).await
                     };
+                    if cancel.is_cancelled() {
+                        return;
+                    }
                     
                     if let Ok(ref key) = result {
                         // Cache the result for future use
                         cached_user_key.set(Some((name.clone(), sec.clone(), key.clone())));
-                        
-                        // Generate identicon if not already set
-                        if identicon.peek().is_empty() {
-                            let mut identicon_bytes = [0u8; 4];
-                            identicon_bytes.copy_from_slice(&key.key_data[0..4]);
-                            identicon.set(spectre_identicon_render(identicon_bytes));
-                        }
                     }
                     result
                 };
@@ -316,7 +334,9 @@ fn Home() -> Element {
                         
                         SiteDomainInput {
                             site_domain,
+                            canonical_site,
                             is_computing_key,
+                            key_progress,
                             on_focus: move |_| {
                                 // Trigger key computation when user focuses site field
                                 trigger_key_computation.set(trigger_key_computation() + 1);
@@ -352,3 +372,31 @@ fn Home() -> Element {
         }
     }
 }
+
+/// Derive the user key on the main thread without freezing the page
+///
+/// The derivation runs in small steps with a timer between them, so input
+/// events and rendering get through and a stale derivation can be cancelled.
+async fn derive_user_key(
+    name: &str,
+    secret: &str,
+    normalization: SpectreNormalization,
+    cancel: &SpectreCancelToken,
+    mut progress: Signal<f32>,
+) -> Result<SpectreUserKey, String> {
+    let mut derivation = SpectreUserKeyDerivation::normalized(name, secret, SPECTRE_ALGORITHM_CURRENT, normalization);
+    progress.set(0.0);
+    
+    while !derivation.step(SPECTRE_DERIVATION_STEP) {
+        progress.set(derivation.progress());
+        sleep(Duration::ZERO).await;
+        
+        if cancel.is_cancelled() {
+            return Err("Key generation cancelled".to_string());
+        }
+    }
+    progress.set(1.0);
+    
+    derivation.finish()
+        .map_err(|e| format!("Key generation failed: {:?}", e))
+}
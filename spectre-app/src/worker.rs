@@ -1,18 +1,20 @@
 use wasm_bindgen::prelude::*;
 use web_sys::{MessageEvent, Worker};
 use serde::{Deserialize, Serialize};
-use spectre::SpectreUserKey;
+use spectre::{SpectreAlgorithm, SpectreNormalization, SpectreUserKey};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum WorkerMessage {
     #[serde(rename = "generate_key")]
-    GenerateKey { name: String, secret: String },
+    GenerateKey { name: String, secret: String, normalization: SpectreNormalization },
     #[serde(rename = "key_result")]
     KeyResult { 
         key_id: Vec<u8>,
         key_data: Vec<u8>,
         algorithm: u32,
+        #[serde(default)]
+        normalization: SpectreNormalization,
     },
     #[serde(rename = "key_error")]
     KeyError { error: String },
@@ -21,14 +23,15 @@ pub enum WorkerMessage {
 impl WorkerMessage {
     pub fn to_spectre_key(&self) -> Option<SpectreUserKey> {
         match self {
-            WorkerMessage::KeyResult { key_id, key_data, algorithm } => {
+            WorkerMessage::KeyResult { key_id, key_data, algorithm, normalization } => {
                 if key_id.len() == 32 {
                     let mut key_id_array = [0u8; 32];
                     key_id_array.copy_from_slice(key_id);
                     Some(SpectreUserKey {
                         key_id: key_id_array,
-                        key_data: key_data.clone(),
-                        algorithm: *algorithm,
+                        key_data: key_data.clone().into(),
+                        algorithm: SpectreAlgorithm::try_from(*algorithm).ok()?,
+                        normalization: *normalization,
                     })
                 } else {
                     None
@@ -42,8 +45,9 @@ impl WorkerMessage {
     pub fn from_spectre_key(key: &SpectreUserKey) -> Self {
         WorkerMessage::KeyResult {
             key_id: key.key_id.to_vec(),
-            key_data: key.key_data.clone(),
-            algorithm: key.algorithm,
+            key_data: key.key_data.to_vec(),
+            algorithm: key.algorithm.into(),
+            normalization: key.normalization,
         }
     }
 }
@@ -73,6 +77,7 @@ impl KeyWorker {
         &self,
         name: String,
         secret: String,
+        normalization: SpectreNormalization,
     ) -> Result<SpectreUserKey, String> {
         let worker = self.worker.as_ref().ok_or("Worker not initialized")?;
         
@@ -112,7 +117,7 @@ impl KeyWorker {
         closure.forget();
         
         // Send message to worker
-        let message = WorkerMessage::GenerateKey { name, secret };
+        let message = WorkerMessage::GenerateKey { name, secret, normalization };
         let message_json = serde_json::to_string(&message)
             .map_err(|e| format!("Serialization error: {}", e))?;
         
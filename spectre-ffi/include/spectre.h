@@ -0,0 +1,299 @@
+/* Spectre C API, generated by cbindgen from spectre-ffi/src/lib.rs. Do not edit. */
+
+#ifndef SPECTRE_H
+#define SPECTRE_H
+
+#include <stdint.h>
+
+/**
+ * A user read by [`spectre_marshal_read`] or made by [`spectre_marshal_user`]
+ */
+typedef struct SpectreMarshalledUser SpectreMarshalledUser;
+
+/**
+ * A user key from [`spectre_user_key`] or [`spectre_marshal_auth`]
+ */
+typedef struct SpectreUserKey SpectreUserKey;
+
+/**
+ * Why the last call on this thread failed, one code per `SpectreError` variant
+ */
+typedef int32_t SpectreErrorCode;
+
+typedef uint32_t SpectreAlgorithm;
+
+/**
+ * Result types, with upstream's codes; device-private passwords are read as
+ * personal ones. Custom templates, one-time codes and mnemonics aren't
+ * upstream's, and have codes above the range upstream uses.
+ */
+typedef uint32_t SpectreResultType;
+
+typedef uint32_t SpectreCounter;
+
+typedef uint32_t SpectreKeyPurpose;
+
+typedef uint32_t SpectreFormat;
+
+#define SpectreAlgorithmV0 0
+
+#define SpectreAlgorithmV1 1
+
+#define SpectreAlgorithmV2 2
+
+#define SpectreAlgorithmV3 3
+
+#define SpectreAlgorithmCurrent SpectreAlgorithmV3
+
+#define SpectreResultNone 0
+
+#define SpectreResultTemplateMaximum 16
+
+#define SpectreResultTemplateLong 17
+
+#define SpectreResultTemplateMedium 18
+
+#define SpectreResultTemplateShort 19
+
+#define SpectreResultTemplateBasic 20
+
+#define SpectreResultTemplatePIN 21
+
+#define SpectreResultTemplateName 30
+
+#define SpectreResultTemplatePhrase 31
+
+#define SpectreResultStatePersonal 1056
+
+#define SpectreResultStateDevice 2081
+
+#define SpectreResultDeriveKey 4160
+
+#define SpectreResultTemplateCustom 65544
+
+#define SpectreResultStateOneTimeCode 131074
+
+#define SpectreResultWordsMnemonic 196608
+
+#define SpectreKeyPurposeAuthentication 0
+
+#define SpectreKeyPurposeIdentification 1
+
+#define SpectreKeyPurposeRecovery 2
+
+#define SpectreCounterInitial 0
+
+#define SpectreCounterDefault 1
+
+#define SpectreFormatNone 0
+
+#define SpectreFormatFlat 1
+
+#define SpectreFormatJSON 2
+
+#define SpectreErrorNone 0
+
+#define SpectreErrorInvalidAlgorithm 1
+
+#define SpectreErrorInvalidResultType 2
+
+#define SpectreErrorInvalidResultParam 3
+
+#define SpectreErrorInvalidTemplate 4
+
+#define SpectreErrorInvalidPolicy 5
+
+#define SpectreErrorInvalidNormalization 6
+
+#define SpectreErrorInvalidSiteName 7
+
+#define SpectreErrorInvalidOtp 8
+
+#define SpectreErrorInvalidKeyPurpose 9
+
+#define SpectreErrorInvalidCounter 10
+
+#define SpectreErrorInvalidFileFormat 11
+
+#define SpectreErrorKeyDerivationFailed 12
+
+#define SpectreErrorCancelled 13
+
+#define SpectreErrorPasswordGenerationFailed 14
+
+#define SpectreErrorEncryptionFailed 15
+
+#define SpectreErrorDecryptionFailed 16
+
+#define SpectreErrorUserSecretMismatch 17
+
+#define SpectreErrorClockUnavailable 18
+
+#define SpectreErrorIo 19
+
+#define SpectreErrorJson 20
+
+#define SpectreErrorSelfTestFailed 21
+
+#define SpectreErrorMissingField 22
+
+/**
+ * A NULL or non-UTF-8 argument, or a result that can't be a C string
+ */
+#define SpectreErrorInvalidArgument 23
+
+#define SpectreErrorSshKey 24
+
+#define SpectreErrorMarshal 25
+
+#define SpectreErrorQuestionConflict 26
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * The error from the last failed call on this thread, or `SpectreErrorNone`
+ * if the last call succeeded
+ */
+SpectreErrorCode spectre_error(void);
+
+/**
+ * A description of the last error on this thread ("" if none)
+ *
+ * The string belongs to the library and stays valid until the next call on
+ * this thread; don't free it.
+ */
+const char *spectre_error_message(void);
+
+/**
+ * Derive the user key from a user's name and secret (slow: runs scrypt)
+ *
+ * # Safety
+ * `userName` and `userSecret` must be NUL-terminated strings.
+ */
+SpectreUserKey *spectre_user_key(const char *userName,
+                                 const char *userSecret,
+                                 SpectreAlgorithm algorithmVersion);
+
+/**
+ * Generate a site result: a password, login name or security answer
+ *
+ * `resultParam` is the stored state for stateful types, the template or
+ * key size for custom and derived types and the word count for word-based
+ * types; NULL otherwise. `keyContext` is NULL for no context.
+ *
+ * # Safety
+ * `userKey` must come from this library; string arguments must be
+ * NUL-terminated (or NULL where allowed).
+ */
+char *spectre_site_result(const SpectreUserKey *userKey,
+                          const char *siteName,
+                          SpectreResultType resultType,
+                          const char *resultParam,
+                          SpectreCounter keyCounter,
+                          SpectreKeyPurpose keyPurpose,
+                          const char *keyContext);
+
+/**
+ * Encrypt a result for storage, e.g. a personal password or one-time code seed
+ *
+ * `resultParam` is the plaintext; pass the returned state as `resultParam`
+ * to [`spectre_site_result`] to get it back.
+ *
+ * # Safety
+ * As for [`spectre_site_result`].
+ */
+char *spectre_site_state(const SpectreUserKey *userKey,
+                         const char *siteName,
+                         SpectreResultType resultType,
+                         const char *resultParam,
+                         SpectreCounter keyCounter,
+                         SpectreKeyPurpose keyPurpose,
+                         const char *keyContext);
+
+/**
+ * The user's identicon in upstream's encoded form, e.g. `2:╚☻╯⛄`
+ * (color, then left arm, body, right arm and accessory)
+ *
+ * # Safety
+ * `userName` and `userSecret` must be NUL-terminated strings.
+ */
+char *spectre_identicon(const char *userName, const char *userSecret);
+
+/**
+ * A new user record, keyed to the user's secret (slow: runs scrypt)
+ *
+ * # Safety
+ * `userName` and `userSecret` must be NUL-terminated strings.
+ */
+SpectreMarshalledUser *spectre_marshal_user(const char *userName,
+                                            const char *userSecret,
+                                            SpectreAlgorithm algorithmVersion);
+
+/**
+ * Read a user file; fails with `SpectreErrorIo` if there is none
+ *
+ * # Safety
+ * `path` must be a NUL-terminated string.
+ */
+SpectreMarshalledUser *spectre_marshal_read(const char *path);
+
+/**
+ * Write a user file, creating its directory if needed
+ *
+ * Redacted users ignore `userKey`, which may be NULL. Unredacted users are
+ * written with their passwords, logins and answers in plaintext, unlocked
+ * with their `userKey`; without one this fails with `SpectreErrorMissingField`,
+ * as it does for a non-ASCII user name with sites of an algorithm that counts
+ * the name's length differently, which need a key of their own.
+ *
+ * # Safety
+ * `path` must be a NUL-terminated string, and `user` and a non-NULL
+ * `userKey` must come from this library.
+ */
+SpectreErrorCode spectre_marshal_write(const char *path,
+                                       SpectreFormat format,
+                                       const SpectreMarshalledUser *user,
+                                       const SpectreUserKey *userKey);
+
+/**
+ * Check a user's secret against their file and derive their user key
+ *
+ * Fails with `SpectreErrorUserSecretMismatch` for the wrong secret. On
+ * success the user's identicon is refreshed.
+ *
+ * # Safety
+ * `user` must come from this library and `userSecret` must be a NUL-terminated string.
+ */
+SpectreUserKey *spectre_marshal_auth(SpectreMarshalledUser *user, const char *userSecret);
+
+/**
+ * Wipe and release a string returned by this library; NULL is ignored
+ *
+ * # Safety
+ * `string` must come from this library and not be used afterwards.
+ */
+void spectre_free_string(char *string);
+
+/**
+ * Wipe and release a user key; NULL is ignored
+ *
+ * # Safety
+ * `userKey` must come from this library and not be used afterwards.
+ */
+void spectre_free_user_key(SpectreUserKey *userKey);
+
+/**
+ * Release a user record; NULL is ignored
+ *
+ * # Safety
+ * `user` must come from this library and not be used afterwards.
+ */
+void spectre_marshal_free(SpectreMarshalledUser *user);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SPECTRE_H */
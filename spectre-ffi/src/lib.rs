@@ -0,0 +1,510 @@
+//! C ABI for spectre-rs
+//!
+//! Functions and constants are named after upstream libspectre
+//! (`spectre-algorithm.h`, `spectre-marshal.h`), so C and Swift tooling can
+//! switch libraries with few changes. The header is generated into
+//! `include/spectre.h` by the build script.
+//!
+//! Strings are NUL-terminated UTF-8. Everything returned is owned by the
+//! caller and released with the matching free function. On failure a function
+//! returns NULL (or an error code), and [`spectre_error`] and
+//! [`spectre_error_message`] describe the failure on the calling thread.
+
+#![allow(non_upper_case_globals)]
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::path::PathBuf;
+use std::ptr;
+use zeroize::{Zeroize, Zeroizing};
+use spectre::SpectreError;
+
+pub type SpectreAlgorithm = u32;
+pub const SpectreAlgorithmV0: SpectreAlgorithm = 0;
+pub const SpectreAlgorithmV1: SpectreAlgorithm = 1;
+pub const SpectreAlgorithmV2: SpectreAlgorithm = 2;
+pub const SpectreAlgorithmV3: SpectreAlgorithm = 3;
+pub const SpectreAlgorithmCurrent: SpectreAlgorithm = SpectreAlgorithmV3;
+
+/// Result types, with upstream's codes; device-private passwords are read as
+/// personal ones. Custom templates, one-time codes and mnemonics aren't
+/// upstream's, and have codes above the range upstream uses.
+pub type SpectreResultType = u32;
+pub const SpectreResultNone: SpectreResultType = 0;
+pub const SpectreResultTemplateMaximum: SpectreResultType = 16;
+pub const SpectreResultTemplateLong: SpectreResultType = 17;
+pub const SpectreResultTemplateMedium: SpectreResultType = 18;
+pub const SpectreResultTemplateShort: SpectreResultType = 19;
+pub const SpectreResultTemplateBasic: SpectreResultType = 20;
+pub const SpectreResultTemplatePIN: SpectreResultType = 21;
+pub const SpectreResultTemplateName: SpectreResultType = 30;
+pub const SpectreResultTemplatePhrase: SpectreResultType = 31;
+pub const SpectreResultStatePersonal: SpectreResultType = 1056;
+pub const SpectreResultStateDevice: SpectreResultType = 2081;
+pub const SpectreResultDeriveKey: SpectreResultType = 4160;
+pub const SpectreResultTemplateCustom: SpectreResultType = 0x00010008;
+pub const SpectreResultStateOneTimeCode: SpectreResultType = 0x00020002;
+pub const SpectreResultWordsMnemonic: SpectreResultType = 0x00030000;
+
+pub type SpectreKeyPurpose = u32;
+pub const SpectreKeyPurposeAuthentication: SpectreKeyPurpose = 0;
+pub const SpectreKeyPurposeIdentification: SpectreKeyPurpose = 1;
+pub const SpectreKeyPurposeRecovery: SpectreKeyPurpose = 2;
+
+pub type SpectreCounter = u32;
+pub const SpectreCounterInitial: SpectreCounter = 0;
+pub const SpectreCounterDefault: SpectreCounter = 1;
+
+pub type SpectreFormat = u32;
+pub const SpectreFormatNone: SpectreFormat = 0;
+pub const SpectreFormatFlat: SpectreFormat = 1;
+pub const SpectreFormatJSON: SpectreFormat = 2;
+
+/// Why the last call on this thread failed, one code per `SpectreError` variant
+pub type SpectreErrorCode = i32;
+pub const SpectreErrorNone: SpectreErrorCode = 0;
+pub const SpectreErrorInvalidAlgorithm: SpectreErrorCode = 1;
+pub const SpectreErrorInvalidResultType: SpectreErrorCode = 2;
+pub const SpectreErrorInvalidResultParam: SpectreErrorCode = 3;
+pub const SpectreErrorInvalidTemplate: SpectreErrorCode = 4;
+pub const SpectreErrorInvalidPolicy: SpectreErrorCode = 5;
+pub const SpectreErrorInvalidNormalization: SpectreErrorCode = 6;
+pub const SpectreErrorInvalidSiteName: SpectreErrorCode = 7;
+pub const SpectreErrorInvalidOtp: SpectreErrorCode = 8;
+pub const SpectreErrorInvalidKeyPurpose: SpectreErrorCode = 9;
+pub const SpectreErrorInvalidCounter: SpectreErrorCode = 10;
+pub const SpectreErrorInvalidFileFormat: SpectreErrorCode = 11;
+pub const SpectreErrorKeyDerivationFailed: SpectreErrorCode = 12;
+pub const SpectreErrorCancelled: SpectreErrorCode = 13;
+pub const SpectreErrorPasswordGenerationFailed: SpectreErrorCode = 14;
+pub const SpectreErrorEncryptionFailed: SpectreErrorCode = 15;
+pub const SpectreErrorDecryptionFailed: SpectreErrorCode = 16;
+pub const SpectreErrorUserSecretMismatch: SpectreErrorCode = 17;
+pub const SpectreErrorClockUnavailable: SpectreErrorCode = 18;
+pub const SpectreErrorIo: SpectreErrorCode = 19;
+pub const SpectreErrorJson: SpectreErrorCode = 20;
+pub const SpectreErrorSelfTestFailed: SpectreErrorCode = 21;
+pub const SpectreErrorMissingField: SpectreErrorCode = 22;
+/// A NULL or non-UTF-8 argument, or a result that can't be a C string
+pub const SpectreErrorInvalidArgument: SpectreErrorCode = 23;
+pub const SpectreErrorSshKey: SpectreErrorCode = 24;
+pub const SpectreErrorMarshal: SpectreErrorCode = 25;
+pub const SpectreErrorQuestionConflict: SpectreErrorCode = 26;
+
+/// A user key from [`spectre_user_key`] or [`spectre_marshal_auth`]
+pub struct SpectreUserKey(spectre::SpectreUserKey);
+
+/// A user read by [`spectre_marshal_read`] or made by [`spectre_marshal_user`]
+pub struct SpectreMarshalledUser(spectre::SpectreMarshalledUser);
+
+fn error_code(error: &SpectreError) -> SpectreErrorCode {
+    match error {
+        SpectreError::InvalidAlgorithm(_) => SpectreErrorInvalidAlgorithm,
+        SpectreError::InvalidResultType(_) => SpectreErrorInvalidResultType,
+        SpectreError::InvalidResultParam(_) => SpectreErrorInvalidResultParam,
+        SpectreError::InvalidTemplate(_) => SpectreErrorInvalidTemplate,
+        SpectreError::InvalidPolicy(_) => SpectreErrorInvalidPolicy,
+        SpectreError::InvalidNormalization(_) => SpectreErrorInvalidNormalization,
+        SpectreError::InvalidSiteName(_) => SpectreErrorInvalidSiteName,
+        SpectreError::InvalidOtp(_) => SpectreErrorInvalidOtp,
+        SpectreError::InvalidKeyPurpose(_) => SpectreErrorInvalidKeyPurpose,
+        SpectreError::InvalidCounter(_) => SpectreErrorInvalidCounter,
+        SpectreError::InvalidFileFormat(_) => SpectreErrorInvalidFileFormat,
+        SpectreError::KeyDerivationFailed => SpectreErrorKeyDerivationFailed,
+        SpectreError::Cancelled => SpectreErrorCancelled,
+        SpectreError::PasswordGenerationFailed => SpectreErrorPasswordGenerationFailed,
+        SpectreError::EncryptionFailed => SpectreErrorEncryptionFailed,
+        SpectreError::DecryptionFailed => SpectreErrorDecryptionFailed,
+        SpectreError::UserSecretMismatch => SpectreErrorUserSecretMismatch,
+        SpectreError::ClockUnavailable => SpectreErrorClockUnavailable,
+        SpectreError::Io(_) => SpectreErrorIo,
+        SpectreError::Json(_) => SpectreErrorJson,
+        SpectreError::Marshal(_) => SpectreErrorMarshal,
+        SpectreError::SshKey(_) => SpectreErrorSshKey,
+        SpectreError::SelfTestFailed(_) => SpectreErrorSelfTestFailed,
+        SpectreError::MissingField(_) => SpectreErrorMissingField,
+        SpectreError::QuestionConflict(_) => SpectreErrorQuestionConflict,
+    }
+}
+
+/// An error on its way to [`spectre_error`]
+struct Failure {
+    code: SpectreErrorCode,
+    message: String,
+}
+
+impl From<SpectreError> for Failure {
+    fn from(error: SpectreError) -> Self {
+        Self { code: error_code(&error), message: error.to_string() }
+    }
+}
+
+fn invalid_argument(message: String) -> Failure {
+    Failure { code: SpectreErrorInvalidArgument, message }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<(SpectreErrorCode, CString)> = RefCell::new((SpectreErrorNone, CString::default()));
+}
+
+/// Run a call, recording how it went for [`spectre_error`]
+fn record<T>(call: impl FnOnce() -> Result<T, Failure>) -> Option<T> {
+    let (value, code, message) = match call() {
+        Ok(value) => (Some(value), SpectreErrorNone, String::new()),
+        Err(failure) => (None, failure.code, failure.message),
+    };
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = (code, message));
+
+    value
+}
+
+/// Borrow a required string argument
+///
+/// # Safety
+/// `string` must be NULL or point to a NUL-terminated string that outlives the call.
+unsafe fn str_arg<'a>(string: *const c_char, name: &str) -> Result<&'a str, Failure> {
+    unsafe { opt_str_arg(string, name) }?
+        .ok_or_else(|| invalid_argument(format!("{} is NULL", name)))
+}
+
+/// Borrow an optional string argument, where NULL means none
+///
+/// # Safety
+/// `string` must be NULL or point to a NUL-terminated string that outlives the call.
+unsafe fn opt_str_arg<'a>(string: *const c_char, name: &str) -> Result<Option<&'a str>, Failure> {
+    if string.is_null() {
+        return Ok(None);
+    }
+    unsafe { CStr::from_ptr(string) }
+        .to_str()
+        .map(Some)
+        .map_err(|_| invalid_argument(format!("{} is not UTF-8", name)))
+}
+
+/// Hand a string to the caller, to be released with [`spectre_free_string`]
+fn c_string(string: String) -> Result<*mut c_char, Failure> {
+    let string = Zeroizing::new(string);
+    CString::new(string.as_bytes())
+        .map(CString::into_raw)
+        .map_err(|_| invalid_argument("result contains a NUL byte".to_string()))
+}
+
+fn user_key_arg<'a>(user_key: *const SpectreUserKey) -> Result<&'a spectre::SpectreUserKey, Failure> {
+    // SAFETY: non-NULL user keys come from Box::into_raw in this library
+    unsafe { user_key.as_ref() }
+        .map(|user_key| &user_key.0)
+        .ok_or_else(|| invalid_argument("userKey is NULL".to_string()))
+}
+
+fn result_type_arg(result_type: SpectreResultType) -> Result<spectre::SpectreResultType, Failure> {
+    Ok(match result_type {
+        SpectreResultTemplateCustom => spectre::SpectreResultType::Custom,
+        SpectreResultStateOneTimeCode => spectre::SpectreResultType::OneTimeCode,
+        SpectreResultWordsMnemonic => spectre::SpectreResultType::Mnemonic,
+        code => spectre::SpectreResultType::from_upstream_code(code)?,
+    })
+}
+
+fn format_arg(format: SpectreFormat) -> Result<spectre::SpectreFormat, Failure> {
+    match format {
+        SpectreFormatNone => Ok(spectre::SpectreFormat::None),
+        SpectreFormatFlat => Ok(spectre::SpectreFormat::Flat),
+        SpectreFormatJSON => Ok(spectre::SpectreFormat::JSON),
+        _ => Err(SpectreError::InvalidFileFormat(format!("unknown format {}", format)).into()),
+    }
+}
+
+/// The error from the last failed call on this thread, or `SpectreErrorNone`
+/// if the last call succeeded
+#[unsafe(no_mangle)]
+pub extern "C" fn spectre_error() -> SpectreErrorCode {
+    LAST_ERROR.with(|last| last.borrow().0)
+}
+
+/// A description of the last error on this thread ("" if none)
+///
+/// The string belongs to the library and stays valid until the next call on
+/// this thread; don't free it.
+#[unsafe(no_mangle)]
+pub extern "C" fn spectre_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().1.as_ptr())
+}
+
+/// Derive the user key from a user's name and secret (slow: runs scrypt)
+///
+/// # Safety
+/// `userName` and `userSecret` must be NUL-terminated strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spectre_user_key(
+    user_name: *const c_char,
+    user_secret: *const c_char,
+    algorithm_version: SpectreAlgorithm,
+) -> *mut SpectreUserKey {
+    record(|| {
+        let user_name = unsafe { str_arg(user_name, "userName") }?;
+        let user_secret = unsafe { str_arg(user_secret, "userSecret") }?;
+        let algorithm = algorithm_version.try_into()?;
+
+        let user_key = spectre::spectre_user_key(user_name, user_secret, algorithm)?;
+        Ok(Box::into_raw(Box::new(SpectreUserKey(user_key))))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Generate a site result: a password, login name or security answer
+///
+/// `resultParam` is the stored state for stateful types, the template or
+/// key size for custom and derived types and the word count for word-based
+/// types; NULL otherwise. `keyContext` is NULL for no context.
+///
+/// # Safety
+/// `userKey` must come from this library; string arguments must be
+/// NUL-terminated (or NULL where allowed).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spectre_site_result(
+    user_key: *const SpectreUserKey,
+    site_name: *const c_char,
+    result_type: SpectreResultType,
+    result_param: *const c_char,
+    key_counter: SpectreCounter,
+    key_purpose: SpectreKeyPurpose,
+    key_context: *const c_char,
+) -> *mut c_char {
+    record(|| {
+        let result = spectre::spectre_site_result(
+            user_key_arg(user_key)?,
+            unsafe { str_arg(site_name, "siteName") }?,
+            result_type_arg(result_type)?,
+            unsafe { opt_str_arg(result_param, "resultParam") }?,
+            key_counter,
+            key_purpose.try_into()?,
+            unsafe { opt_str_arg(key_context, "keyContext") }?,
+        )?;
+        c_string(result)
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Encrypt a result for storage, e.g. a personal password or one-time code seed
+///
+/// `resultParam` is the plaintext; pass the returned state as `resultParam`
+/// to [`spectre_site_result`] to get it back.
+///
+/// # Safety
+/// As for [`spectre_site_result`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spectre_site_state(
+    user_key: *const SpectreUserKey,
+    site_name: *const c_char,
+    result_type: SpectreResultType,
+    result_param: *const c_char,
+    key_counter: SpectreCounter,
+    key_purpose: SpectreKeyPurpose,
+    key_context: *const c_char,
+) -> *mut c_char {
+    record(|| {
+        let state = spectre::spectre_site_state(
+            user_key_arg(user_key)?,
+            unsafe { str_arg(site_name, "siteName") }?,
+            result_type_arg(result_type)?,
+            unsafe { str_arg(result_param, "resultParam") }?,
+            key_counter,
+            key_purpose.try_into()?,
+            unsafe { opt_str_arg(key_context, "keyContext") }?,
+        )?;
+        c_string(state)
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// The user's identicon in upstream's encoded form, e.g. `2:╚☻╯⛄`
+/// (color, then left arm, body, right arm and accessory)
+///
+/// # Safety
+/// `userName` and `userSecret` must be NUL-terminated strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spectre_identicon(user_name: *const c_char, user_secret: *const c_char) -> *mut c_char {
+    record(|| {
+        let user_name = unsafe { str_arg(user_name, "userName") }?;
+        let user_secret = unsafe { str_arg(user_secret, "userSecret") }?;
+        c_string(spectre::spectre_identicon(user_name, user_secret).encode())
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// A new user record, keyed to the user's secret (slow: runs scrypt)
+///
+/// # Safety
+/// `userName` and `userSecret` must be NUL-terminated strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spectre_marshal_user(
+    user_name: *const c_char,
+    user_secret: *const c_char,
+    algorithm_version: SpectreAlgorithm,
+) -> *mut SpectreMarshalledUser {
+    record(|| {
+        let user_name = unsafe { str_arg(user_name, "userName") }?;
+        let user_secret = unsafe { str_arg(user_secret, "userSecret") }?;
+        let algorithm = algorithm_version.try_into()?;
+
+        let user_key = spectre::spectre_user_key(user_name, user_secret, algorithm)?;
+        let user = spectre::SpectreMarshalledUser::new(
+            user_name.to_string(),
+            spectre::spectre_identicon(user_name, user_secret),
+            user_key.key_id,
+            algorithm,
+        );
+        Ok(Box::into_raw(Box::new(SpectreMarshalledUser(user))))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Read a user file; fails with `SpectreErrorIo` if there is none
+///
+/// # Safety
+/// `path` must be a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spectre_marshal_read(path: *const c_char) -> *mut SpectreMarshalledUser {
+    record(|| {
+        let path = PathBuf::from(unsafe { str_arg(path, "path") }?);
+        match spectre::spectre_marshal_read(&path)? {
+            (_, Some(user)) => Ok(Box::into_raw(Box::new(SpectreMarshalledUser(user)))),
+            (_, None) => Err(SpectreError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no user file at {}", path.display()),
+            )).into()),
+        }
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Write a user file, creating its directory if needed
+///
+/// Redacted users ignore `userKey`, which may be NULL. Unredacted users are
+/// written with their passwords, logins and answers in plaintext, unlocked
+/// with their `userKey`; without one this fails with `SpectreErrorMissingField`,
+/// as it does for a non-ASCII user name with sites of an algorithm that counts
+/// the name's length differently, which need a key of their own.
+///
+/// # Safety
+/// `path` must be a NUL-terminated string, and `user` and a non-NULL
+/// `userKey` must come from this library.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spectre_marshal_write(
+    path: *const c_char,
+    format: SpectreFormat,
+    user: *const SpectreMarshalledUser,
+    user_key: *const SpectreUserKey,
+) -> SpectreErrorCode {
+    record(|| {
+        let path = PathBuf::from(unsafe { str_arg(path, "path") }?);
+        let user = unsafe { user.as_ref() }.ok_or_else(|| invalid_argument("user is NULL".to_string()))?;
+        // SAFETY: non-NULL user keys come from Box::into_raw in this library
+        let user_keys = unsafe { user_key.as_ref() }.map(|user_key| std::slice::from_ref(&user_key.0)).unwrap_or_default();
+        Ok(spectre::spectre_marshal_write(&path, format_arg(format)?, &user.0, user_keys)?)
+    });
+
+    spectre_error()
+}
+
+/// Check a user's secret against their file and derive their user key
+///
+/// Fails with `SpectreErrorUserSecretMismatch` for the wrong secret. On
+/// success the user's identicon is refreshed.
+///
+/// # Safety
+/// `user` must come from this library and `userSecret` must be a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spectre_marshal_auth(
+    user: *mut SpectreMarshalledUser,
+    user_secret: *const c_char,
+) -> *mut SpectreUserKey {
+    record(|| {
+        let user = unsafe { user.as_mut() }.ok_or_else(|| invalid_argument("user is NULL".to_string()))?;
+        let user_secret = unsafe { str_arg(user_secret, "userSecret") }?;
+
+        let user_key = spectre::spectre_marshal_auth(&mut user.0, user_secret)?;
+        Ok(Box::into_raw(Box::new(SpectreUserKey(user_key))))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Wipe and release a string returned by this library; NULL is ignored
+///
+/// # Safety
+/// `string` must come from this library and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spectre_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        unsafe { CString::from_raw(string) }.into_bytes_with_nul().zeroize();
+    }
+}
+
+/// Wipe and release a user key; NULL is ignored
+///
+/// # Safety
+/// `userKey` must come from this library and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spectre_free_user_key(user_key: *mut SpectreUserKey) {
+    if !user_key.is_null() {
+        drop(unsafe { Box::from_raw(user_key) });
+    }
+}
+
+/// Release a user record; NULL is ignored
+///
+/// # Safety
+/// `user` must come from this library and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spectre_marshal_free(user: *mut SpectreMarshalledUser) {
+    if !user.is_null() {
+        drop(unsafe { Box::from_raw(user) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spectre::{SpectreKeyPurpose as Purpose, SpectreResultType as Type};
+
+    #[test]
+    fn test_constants_match_the_crate() {
+        let types = [
+            (SpectreResultNone, Type::None),
+            (SpectreResultTemplateMaximum, Type::MaximumSecurityPassword),
+            (SpectreResultTemplateLong, Type::LongPassword),
+            (SpectreResultTemplateMedium, Type::MediumPassword),
+            (SpectreResultTemplateShort, Type::ShortPassword),
+            (SpectreResultTemplateBasic, Type::BasicPassword),
+            (SpectreResultTemplatePIN, Type::PIN),
+            (SpectreResultTemplateName, Type::Name),
+            (SpectreResultTemplatePhrase, Type::Phrase),
+            (SpectreResultStatePersonal, Type::PersonalPassword),
+            (SpectreResultDeriveKey, Type::DeriveKey),
+        ];
+        for (code, result_type) in types {
+            assert_eq!(result_type_arg(code).ok(), Some(result_type));
+            assert_eq!(result_type.upstream_code(), Some(code));
+        }
+        assert_eq!(result_type_arg(SpectreResultStateDevice).ok(), Some(Type::PersonalPassword));
+
+        let extensions = [
+            (SpectreResultTemplateCustom, Type::Custom),
+            (SpectreResultStateOneTimeCode, Type::OneTimeCode),
+            (SpectreResultWordsMnemonic, Type::Mnemonic),
+        ];
+        for (code, result_type) in extensions {
+            assert_eq!(result_type_arg(code).ok(), Some(result_type));
+            assert_eq!(result_type.upstream_code(), None);
+            assert!(Type::from_upstream_code(code).is_err());
+        }
+        assert!(result_type_arg(0x00010001).is_err());
+
+        assert_eq!(Purpose::try_from(SpectreKeyPurposeAuthentication).unwrap(), Purpose::Authentication);
+        assert_eq!(Purpose::try_from(SpectreKeyPurposeIdentification).unwrap(), Purpose::Identification);
+        assert_eq!(Purpose::try_from(SpectreKeyPurposeRecovery).unwrap(), Purpose::Recovery);
+        assert_eq!(spectre::SPECTRE_ALGORITHM_CURRENT.version(), SpectreAlgorithmCurrent);
+        assert_eq!(spectre::SPECTRE_COUNTER_DEFAULT, SpectreCounterDefault);
+    }
+}
@@ -0,0 +1,110 @@
+/* Checks the C API against the known vectors; exits non-zero on any mismatch. */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include "spectre.h"
+
+static int failures = 0;
+
+static void expect_string(const char *what, char *actual, const char *expected) {
+    if (!actual) {
+        fprintf(stderr, "FAIL %s: error %d (%s)\n", what, spectre_error(), spectre_error_message());
+        failures++;
+        return;
+    }
+    if (strcmp(actual, expected) != 0) {
+        fprintf(stderr, "FAIL %s: got \"%s\", expected \"%s\"\n", what, actual, expected);
+        failures++;
+    }
+    spectre_free_string(actual);
+}
+
+static void expect_error(const char *what, SpectreErrorCode expected) {
+    if (spectre_error() != expected) {
+        fprintf(stderr, "FAIL %s: error %d (%s), expected %d\n", what, spectre_error(), spectre_error_message(), expected);
+        failures++;
+    }
+}
+
+int main(int argc, char **argv) {
+    const char *userName = "Robert Lee Mitchell", *userSecret = "banana colored duckling";
+    const char *siteName = "masterpasswordapp.com";
+
+    SpectreUserKey *userKey = spectre_user_key(userName, userSecret, SpectreAlgorithmV3);
+    if (!userKey) {
+        fprintf(stderr, "FAIL user key: %s\n", spectre_error_message());
+        return 1;
+    }
+
+    expect_string("long password", spectre_site_result(
+            userKey, siteName, SpectreResultTemplateLong, NULL, SpectreCounterDefault, SpectreKeyPurposeAuthentication, NULL),
+        "Jejr5[RepuSosp");
+    expect_string("PIN", spectre_site_result(
+            userKey, siteName, SpectreResultTemplatePIN, NULL, SpectreCounterDefault, SpectreKeyPurposeAuthentication, NULL),
+        "7662");
+    expect_string("login", spectre_site_result(
+            userKey, siteName, SpectreResultTemplateName, NULL, SpectreCounterDefault, SpectreKeyPurposeIdentification, NULL),
+        "wohzaqage");
+    expect_string("security answer", spectre_site_result(
+            userKey, siteName, SpectreResultTemplatePhrase, NULL, SpectreCounterDefault, SpectreKeyPurposeRecovery, "question"),
+        "xogx tem cegyiva jab");
+    expect_string("identicon", spectre_identicon(userName, userSecret), "2:╚☻╯⛄");
+
+    /* Personal passwords round-trip through their encrypted state */
+    char *state = spectre_site_state(
+        userKey, siteName, SpectreResultStatePersonal, "correct horse", SpectreCounterDefault, SpectreKeyPurposeAuthentication, NULL);
+    expect_string("personal password", spectre_site_result(
+            userKey, siteName, SpectreResultStatePersonal, state, SpectreCounterDefault, SpectreKeyPurposeAuthentication, NULL),
+        "correct horse");
+    spectre_free_string(state);
+
+    /* Errors come back as NULL plus an error code */
+    if (spectre_site_result(userKey, siteName, 0x12345, NULL, SpectreCounterDefault, SpectreKeyPurposeAuthentication, NULL)) {
+        fprintf(stderr, "FAIL bad result type: got a result\n");
+        failures++;
+    }
+    expect_error("bad result type", SpectreErrorInvalidResultType);
+    if (spectre_site_result(userKey, NULL, SpectreResultTemplateLong, NULL, SpectreCounterDefault, SpectreKeyPurposeAuthentication, NULL)) {
+        fprintf(stderr, "FAIL NULL site name: got a result\n");
+        failures++;
+    }
+    expect_error("NULL site name", SpectreErrorInvalidArgument);
+    spectre_free_user_key(userKey);
+
+    /* A user file round-trips and authenticates only with the right secret */
+    if (argc > 1) {
+        const char *path = argv[1];
+        SpectreMarshalledUser *user = spectre_marshal_user(userName, userSecret, SpectreAlgorithmCurrent);
+        if (spectre_marshal_write(path, SpectreFormatJSON, user, NULL) != SpectreErrorNone) {
+            fprintf(stderr, "FAIL marshal write: %s\n", spectre_error_message());
+            failures++;
+        }
+        spectre_marshal_free(user);
+
+        user = spectre_marshal_read(path);
+        if (!user) {
+            fprintf(stderr, "FAIL marshal read: %s\n", spectre_error_message());
+            return 1;
+        }
+        if (spectre_marshal_auth(user, "banana colored ducklings")) {
+            fprintf(stderr, "FAIL marshal auth: accepted the wrong secret\n");
+            failures++;
+        }
+        expect_error("wrong secret", SpectreErrorUserSecretMismatch);
+
+        userKey = spectre_marshal_auth(user, userSecret);
+        expect_string("long password from file", spectre_site_result(
+                userKey, siteName, SpectreResultTemplateLong, NULL, SpectreCounterDefault, SpectreKeyPurposeAuthentication, NULL),
+            "Jejr5[RepuSosp");
+        spectre_free_user_key(userKey);
+        spectre_marshal_free(user);
+    }
+
+    if (failures) {
+        fprintf(stderr, "%d check(s) failed\n", failures);
+        return 1;
+    }
+    printf("All C API checks passed\n");
+    return 0;
+}
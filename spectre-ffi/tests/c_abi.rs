@@ -0,0 +1,45 @@
+//! Build and run the C test program against the static library
+//!
+//! Skipped when no C compiler (`cc`, or `$CC`) is available.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The directory cargo put this crate's libraries in (`target/<profile>`)
+fn library_dir() -> PathBuf {
+    let test_exe = env::current_exe().expect("test binary path");
+    test_exe.parent().and_then(Path::parent).expect("target directory").to_path_buf()
+}
+
+#[test]
+fn test_c_program() {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let compiler = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    if Command::new(&compiler).arg("--version").output().is_err() {
+        println!("{} not found, skipping the C test program", compiler);
+        return;
+    }
+
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let program = out_dir.join("spectre_c_test");
+    let output = Command::new(&compiler)
+        .arg(crate_dir.join("tests/c/test_vectors.c"))
+        .arg("-I").arg(crate_dir.join("include"))
+        .arg(library_dir().join("libspectre_ffi.a"))
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&program)
+        .output()
+        .expect("failed to run the C compiler");
+    assert!(output.status.success(), "C test program didn't build:\n{}", String::from_utf8_lossy(&output.stderr));
+
+    let user_file = out_dir.join("c_abi").join("Robert Lee Mitchell.mpsites.json");
+    let _ = std::fs::remove_file(&user_file);
+    let output = Command::new(&program).arg(&user_file).output().expect("failed to run the C test program");
+    assert!(
+        output.status.success(),
+        "C test program failed:\n{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr),
+    );
+}
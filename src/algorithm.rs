@@ -1,67 +1,113 @@
+use alloc::{string::{String, ToString}, vec, vec::Vec};
+use aes::Aes128;
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit, block_padding::Pkcs7};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
 use hmac::{Hmac, Mac};
 use sha2::{Sha256, Digest};
 use scrypt::{scrypt, Params};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+use crate::batch::SpectreSiteRequest;
 use crate::error::{Result, SpectreError};
+use crate::otp::SpectreOtp;
+use crate::secret::SpectreSecretBytes;
 use crate::types::*;
+use crate::words::spectre_words_result;
 
-type HmacSha256 = Hmac<Sha256>;
+pub(crate) type HmacSha256 = Hmac<Sha256>;
+type Aes128CbcEnc = cbc::Encryptor<Aes128>;
+type Aes128CbcDec = cbc::Decryptor<Aes128>;
 
-const SPECTRE_N: u32 = 32768;
-const SPECTRE_R: u32 = 8;
-const SPECTRE_P: u32 = 2;
-const SPECTRE_DK_LEN: usize = 64;
+pub(crate) const SPECTRE_N: u32 = 32768;
+pub(crate) const SPECTRE_R: u32 = 8;
+pub(crate) const SPECTRE_P: u32 = 2;
+pub(crate) const SPECTRE_DK_LEN: usize = 64;
 
-#[derive(Clone)]
+/// A derived user key; the key data is wiped from memory when dropped
+#[derive(Debug, Clone)]
 pub struct SpectreUserKey {
     pub key_id: [u8; 32],
-    pub key_data: Vec<u8>,
+    pub key_data: SpectreSecretBytes,
     pub algorithm: SpectreAlgorithm,
+    /// How the user's input was normalized; site names and contexts follow suit
+    pub normalization: SpectreNormalization,
 }
 
-/// Derive a user key from the user's name and secret
+impl SpectreUserKey {
+    /// Wrap derived key data, computing its key ID (SHA256 of the key)
+    pub(crate) fn from_key_data(
+        key_data: SpectreSecretBytes,
+        algorithm: SpectreAlgorithm,
+        normalization: SpectreNormalization,
+    ) -> Self {
+        let mut key_id = [0u8; 32];
+        key_id.copy_from_slice(&Sha256::digest(&key_data));
+
+        SpectreUserKey {
+            key_id,
+            key_data,
+            algorithm,
+            normalization,
+        }
+    }
+
+    /// Compare against a stored key ID in constant time
+    pub fn matches_key_id(&self, key_id: &[u8; 32]) -> bool {
+        self.key_id.ct_eq(key_id).into()
+    }
+
+    /// Whether this key is also `user_name`'s key for `algorithm`
+    ///
+    /// Keys of different algorithms only differ in how the user name's length
+    /// is counted (characters before v3, bytes since), so for ASCII names one
+    /// key covers every algorithm. Other names need one key for each way.
+    pub fn covers_algorithm(&self, user_name: &str, algorithm: SpectreAlgorithm) -> bool {
+        user_name.is_ascii() || algorithm.user_name_byte_length() == self.algorithm.user_name_byte_length()
+    }
+}
+
+/// Derive a user key from the user's name and secret, normalized to NFC
 pub fn spectre_user_key(
     user_name: &str,
     user_secret: &str,
     algorithm: SpectreAlgorithm,
 ) -> Result<SpectreUserKey> {
-    if !(SPECTRE_ALGORITHM_FIRST..=SPECTRE_ALGORITHM_LAST).contains(&algorithm) {
-        return Err(SpectreError::InvalidAlgorithm(algorithm));
-    }
+    spectre_user_key_normalized(user_name, user_secret, algorithm, SpectreNormalization::default())
+}
 
-    // Create salt: "com.lyndir.masterpassword" + user_name length + user_name
-    let scope = b"com.lyndir.masterpassword";
-    let name_bytes = user_name.as_bytes();
-    let name_len = (name_bytes.len() as u32).to_be_bytes();
-    
-    let mut salt = Vec::with_capacity(scope.len() + 4 + name_bytes.len());
-    salt.extend_from_slice(scope);
-    salt.extend_from_slice(&name_len);
-    salt.extend_from_slice(name_bytes);
+/// Derive a user key, normalizing the name and secret as given
+pub fn spectre_user_key_normalized(
+    user_name: &str,
+    user_secret: &str,
+    algorithm: SpectreAlgorithm,
+    normalization: SpectreNormalization,
+) -> Result<SpectreUserKey> {
+    let salt = spectre_user_key_salt(&normalization.apply(user_name), algorithm);
+    let user_secret = Zeroizing::new(normalization.apply(user_secret).into_owned());
 
     // Derive key using scrypt
-    let mut key_data = vec![0u8; SPECTRE_DK_LEN];
+    let mut key_data = SpectreSecretBytes::new(vec![0u8; SPECTRE_DK_LEN]);
     let params = Params::new(
-        (SPECTRE_N as f64).log2() as u8,
+        SPECTRE_N.trailing_zeros() as u8,
         SPECTRE_R,
         SPECTRE_P,
         SPECTRE_DK_LEN,
     ).map_err(|_| SpectreError::KeyDerivationFailed)?;
     
-    scrypt(user_secret.as_bytes(), &salt, &params, &mut key_data)
+    scrypt(user_secret.as_bytes(), &salt, &params, key_data.as_mut_bytes())
         .map_err(|_| SpectreError::KeyDerivationFailed)?;
 
-    // Generate key ID (SHA256 of the key)
-    let mut hasher = Sha256::new();
-    hasher.update(&key_data);
-    let key_id_vec = hasher.finalize();
-    let mut key_id = [0u8; 32];
-    key_id.copy_from_slice(&key_id_vec);
-
-    Ok(SpectreUserKey {
-        key_id,
-        key_data,
-        algorithm,
-    })
+    Ok(SpectreUserKey::from_key_data(key_data, algorithm, normalization))
+}
+
+/// Build the scrypt salt: "com.lyndir.masterpassword" + user_name length + user_name
+pub(crate) fn spectre_user_key_salt(user_name: &str, algorithm: SpectreAlgorithm) -> Vec<u8> {
+    let scope = b"com.lyndir.masterpassword";
+
+    let mut salt = Vec::with_capacity(scope.len() + 4 + user_name.len());
+    salt.extend_from_slice(scope);
+    push_string(&mut salt, user_name, algorithm.user_name_byte_length());
+    salt
 }
 
 /// Generate a site password
@@ -74,23 +120,93 @@ pub fn spectre_site_result(
     key_purpose: SpectreKeyPurpose,
     key_context: Option<&str>,
 ) -> Result<String> {
+    let request = SpectreSiteRequest {
+        site_name,
+        result_type,
+        result_param,
+        key_counter,
+        key_purpose,
+        key_context,
+        policy: None,
+    };
+
+    spectre_site_request_result(user_key, &request)
+}
+
+/// Generate the result for one [`SpectreSiteRequest`], honouring its password policy
+pub fn spectre_site_request_result(user_key: &SpectreUserKey, request: &SpectreSiteRequest) -> Result<String> {
+    spectre_site_result_keyed(user_key, &spectre_user_key_mac(user_key), request)
+}
+
+/// Generate a site result with the user key's HMAC key schedule already prepared
+pub(crate) fn spectre_site_result_keyed(
+    user_key: &SpectreUserKey,
+    mac: &HmacSha256,
+    request: &SpectreSiteRequest,
+) -> Result<String> {
+    let &SpectreSiteRequest {
+        site_name,
+        result_type,
+        result_param,
+        key_counter,
+        key_purpose,
+        key_context,
+        ..
+    } = request;
+
     // Derive site key
     let site_key = spectre_site_key(
-        user_key,
+        user_key.algorithm,
+        user_key.normalization,
+        mac,
         site_name,
         key_counter,
         key_purpose,
         key_context,
-    )?;
+    );
 
-    // For stateful types, handle differently
+    // For one-time codes, the stored seed gives the current code
+    if result_type.is_one_time_code() {
+        let state = result_param.ok_or(SpectreError::PasswordGenerationFailed)?;
+        let otp = spectre_otp_decrypt(&site_key, state)?;
+        return Ok(otp.code(spectre_unix_time()?));
+    }
+
+    // For stateful types, decrypt the stored state
     if result_type.is_stateful() {
         if let Some(state) = result_param {
-            return Ok(state.to_string());
+            return spectre_state_decrypt(&user_key.key_data, state);
         }
         return Err(SpectreError::PasswordGenerationFailed);
     }
 
+    // For derived types, the site key seeds the key material
+    if result_type.is_derived() {
+        let param = result_param
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or_default();
+        return Ok(spectre_derive_key(&site_key, param));
+    }
+
+    // Word-based results pick from their word list
+    if let Some(list) = result_type.word_list() {
+        let param = result_param
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or_default();
+        return Ok(spectre_words_result(&site_key, list, &param));
+    }
+
+    // Custom results bring their own (single) template
+    if result_type.is_custom() {
+        let template: SpectreTemplate = result_param
+            .ok_or_else(|| SpectreError::MissingField("custom template".to_string()))?
+            .parse()?;
+        let password = spectre_template_result(user_key.algorithm, &site_key, template.as_str());
+        return spectre_policy_result(request, &site_key, password);
+    }
+
     // Generate password from template
     let templates = result_type.template();
     if templates.is_empty() {
@@ -98,10 +214,24 @@ pub fn spectre_site_result(
     }
 
     // Select template based on seed
-    let template_index = site_key[0] as usize % templates.len();
+    let algorithm = user_key.algorithm;
+    let template_index = template_seed(algorithm, site_key[0]) % templates.len();
     let template = templates[template_index];
 
-    // Generate password from template
+    let password = spectre_template_result(algorithm, &site_key, template);
+    spectre_policy_result(request, &site_key, password)
+}
+
+/// Bring a template result in line with the request's password policy, if any
+fn spectre_policy_result(request: &SpectreSiteRequest, site_key: &[u8], password: String) -> Result<String> {
+    match request.policy {
+        Some(policy) => policy.apply(site_key, password),
+        None => Ok(password),
+    }
+}
+
+/// Fill a template's character classes from the site key
+fn spectre_template_result(algorithm: SpectreAlgorithm, site_key: &[u8], template: &str) -> String {
     let mut password = String::new();
     let mut seed_index = 1; // Start at 1 since 0 was used for template selection
     for template_char in template.chars() {
@@ -109,137 +239,205 @@ pub fn spectre_site_result(
         if char_class.is_empty() {
             password.push(template_char);
         } else {
-            let char_index = site_key[seed_index % site_key.len()] as usize % char_class.len();
-            password.push(char_class[char_index]);
+            let seed = template_seed(algorithm, site_key[seed_index % site_key.len()]);
+            password.push(char_class[seed % char_class.len()]);
             seed_index += 1;
         }
     }
 
-    Ok(password)
+    password
+}
+
+/// Derive key material from a site key.
+///
+/// Like upstream, this is a BLAKE2b hash of an empty message keyed with the
+/// site key, with the digest length set to the requested key size.
+fn spectre_derive_key(site_key: &[u8], param: SpectreDeriveKeyParam) -> String {
+    let key = blake2b_simd::Params::new()
+        .hash_length(param.bits / 8)
+        .key(site_key)
+        .hash(&[]);
+    
+    match param.encoding {
+        SpectreKeyEncoding::Base64 => BASE64.encode(key.as_bytes()),
+        SpectreKeyEncoding::Hex => {
+            use core::fmt::Write;
+            
+            let mut result = String::new();
+            for byte in key.as_bytes() {
+                write!(&mut result, "{:02x}", byte).unwrap();
+            }
+            result
+        }
+    }
+}
+
+/// Read a site key byte as a template seed.
+///
+/// Algorithm v0 cast the (signed) byte to a 16-bit integer and then stored it
+/// big-endian into a native (little-endian) integer, so `0x7f` became `0x7f00`
+/// and `0x80` became `0x80ff`.
+fn template_seed(algorithm: SpectreAlgorithm, byte: u8) -> usize {
+    if algorithm.signed_template_seed() {
+        (byte as i8 as u16).swap_bytes() as usize
+    } else {
+        byte as usize
+    }
+}
+
+/// Append a 4-byte big-endian length followed by the string's bytes.
+///
+/// Older algorithm versions counted characters rather than bytes for the length.
+fn push_string(salt: &mut Vec<u8>, value: &str, byte_length: bool) {
+    let length = if byte_length {
+        value.len()
+    } else {
+        value.chars().count()
+    };
+    salt.extend_from_slice(&(length as u32).to_be_bytes());
+    salt.extend_from_slice(value.as_bytes());
+}
+
+/// Key HMAC-SHA256 with the user key, once for any number of site keys
+pub(crate) fn spectre_user_key_mac(user_key: &SpectreUserKey) -> HmacSha256 {
+    HmacSha256::new_from_slice(&user_key.key_data)
+        .expect("HMAC accepts keys of any length")
 }
 
 /// Derive a site-specific key
-fn spectre_site_key(
-    user_key: &SpectreUserKey,
+pub(crate) fn spectre_site_key(
+    algorithm: SpectreAlgorithm,
+    normalization: SpectreNormalization,
+    mac: &HmacSha256,
     site_name: &str,
     key_counter: SpectreCounter,
     key_purpose: SpectreKeyPurpose,
     key_context: Option<&str>,
-) -> Result<Vec<u8>> {
+) -> SpectreSecretBytes {
     let scope: &[u8] = match key_purpose {
         SpectreKeyPurpose::Authentication => b"com.lyndir.masterpassword",
         SpectreKeyPurpose::Identification => b"com.lyndir.masterpassword.login",
         SpectreKeyPurpose::Recovery => b"com.lyndir.masterpassword.answer",
     };
 
-    let site_bytes = site_name.as_bytes();
-    let site_len = (site_bytes.len() as u32).to_be_bytes();
+    let byte_length = algorithm.site_name_byte_length();
 
     let mut salt = Vec::new();
     salt.extend_from_slice(scope);
-    salt.extend_from_slice(&site_len);
-    salt.extend_from_slice(site_bytes);
+    push_string(&mut salt, &normalization.apply(site_name), byte_length);
     salt.extend_from_slice(&key_counter.to_be_bytes());
 
-    if let Some(context) = key_context {
-        if !context.is_empty() {
-            let context_bytes = context.as_bytes();
-            let context_len = (context_bytes.len() as u32).to_be_bytes();
-            salt.extend_from_slice(&context_len);
-            salt.extend_from_slice(context_bytes);
+    if let Some(context) = key_context
+        && !context.is_empty() {
+            push_string(&mut salt, &normalization.apply(context), byte_length);
         }
-    }
 
     // Use HMAC-SHA256 to derive site key
-    let mut mac = HmacSha256::new_from_slice(&user_key.key_data)
-        .map_err(|_| SpectreError::KeyDerivationFailed)?;
+    let mut mac = mac.clone();
     mac.update(&salt);
     let result = mac.finalize();
     
-    Ok(result.into_bytes().to_vec())
-}
-
-/// Generate an identicon for a user
-pub fn spectre_identicon(user_name: &str, user_secret: &str) -> Result<[u8; 4]> {
-    let user_key = spectre_user_key(user_name, user_secret, SPECTRE_ALGORITHM_CURRENT)?;
-    
-    let mut identicon = [0u8; 4];
-    identicon.copy_from_slice(&user_key.key_data[0..4]);
-    
-    Ok(identicon)
-}
-
-/// Generate a fast preview identicon using SHA256 (not the real identicon, but instant feedback)
-/// This can be used while typing before the expensive scrypt computation
-pub fn spectre_identicon_preview(user_name: &str, user_secret: &str) -> [u8; 4] {
-    // Use SHA256 for instant feedback (much faster than scrypt)
-    let mut hasher = Sha256::new();
-    hasher.update(b"com.lyndir.masterpassword.preview");
-    hasher.update(&(user_name.len() as u32).to_be_bytes());
-    hasher.update(user_name.as_bytes());
-    hasher.update(user_secret.as_bytes());
-    let hash = hasher.finalize();
-    
-    let mut identicon = [0u8; 4];
-    identicon.copy_from_slice(&hash[0..4]);
-    identicon
-}
-
-/// Render an identicon as a visual string
-pub fn spectre_identicon_render(identicon: [u8; 4]) -> String {
-    const IDENTICON_ACCESSORIES: &[&str] = &[
-        "💠", "🎯", "🌗", "🌓", "🌘", "🌔", "☀️", "☁️", "☂️", "☃️", 
-        "",   "⭐", "✨", "☎️", "📞", "☸️", "🏠", "☘️", "☢️", "☣️", 
-        "☕️", "⌚️", "⌛️", "⏰️", "⚡️", "⛄️", "⛅️", "☔️", "👑", "👸", 
-        "🏰", "⛪️", "🐎", "♟️", "🤴", "👸", "🏰", "⛪️", "🏇", "♟️", 
-        "♨️", "🎵", "🎶", "🎼", "🏳️", "🏴", "⚔️", "⚖️", "⚙️", "⚠️", 
-        "🕹️", "↩️", "✂️", "📲", "✈️", "✉️", "✌️",
-    ];
-    
-    let mut result = String::new();
-    for &byte in &identicon {
-        let index = (byte as usize) % IDENTICON_ACCESSORIES.len();
-        result.push_str(IDENTICON_ACCESSORIES[index]);
-    }
-    
-    result
+    SpectreSecretBytes::new(result.into_bytes().to_vec())
 }
 
 /// Encrypt a personal password (stateful)
+///
+/// The state is compatible with upstream Spectre: AES-128-CBC keyed with
+/// `user_key[..16]`, zero IV and PKCS#7 padding, base64 encoded. It doesn't
+/// depend on the site, counter or purpose.
+///
+/// One-time code seeds (any form [`SpectreOtp`] parses) are instead stored
+/// as an `otpauth://` URI encrypted the same way under the site key, so
+/// they only open for this site, counter and purpose.
 pub fn spectre_site_state(
     user_key: &SpectreUserKey,
     site_name: &str,
-    _result_type: SpectreResultType,
+    result_type: SpectreResultType,
     plaintext: &str,
     key_counter: SpectreCounter,
     key_purpose: SpectreKeyPurpose,
     key_context: Option<&str>,
 ) -> Result<String> {
-    // For now, just base64 encode (in production, should use proper encryption)
-    // This is a simplified version - the C implementation uses proper AES encryption
-    use std::fmt::Write;
+    if result_type.is_one_time_code() {
+        let site_key = spectre_site_key(
+            user_key.algorithm,
+            user_key.normalization,
+            &spectre_user_key_mac(user_key),
+            site_name,
+            key_counter,
+            key_purpose,
+            key_context,
+        );
+        let otp: SpectreOtp = plaintext.parse()?;
+        return spectre_state_encrypt(&site_key, &Zeroizing::new(otp.uri()));
+    }
     
+    spectre_state_encrypt(&user_key.key_data, plaintext)
+}
+
+/// Open the one-time code seed stored for a site by [`spectre_site_state`]
+///
+/// The request's `result_param` holds the state. HOTP seeds must be
+/// [advanced](SpectreOtp::advance) and stored again after each code is used.
+pub fn spectre_site_otp(user_key: &SpectreUserKey, request: &SpectreSiteRequest) -> Result<SpectreOtp> {
+    let state = request.result_param
+        .ok_or_else(|| SpectreError::MissingField("one-time code seed".to_string()))?;
     let site_key = spectre_site_key(
-        user_key,
-        site_name,
-        key_counter,
-        key_purpose,
-        key_context,
-    )?;
+        user_key.algorithm,
+        user_key.normalization,
+        &spectre_user_key_mac(user_key),
+        request.site_name,
+        request.key_counter,
+        request.key_purpose,
+        request.key_context,
+    );
     
-    // XOR encrypt (simplified - should use AES in production)
-    let mut encrypted = Vec::new();
-    for (i, &byte) in plaintext.as_bytes().iter().enumerate() {
-        encrypted.push(byte ^ site_key[i % site_key.len()]);
+    spectre_otp_decrypt(&site_key, state)
+}
+
+/// Seconds since the Unix epoch, for time-based one-time codes
+///
+/// Without `std` there is no clock to read; open the seed with
+/// [`spectre_site_otp`] and pass the device's time to [`SpectreOtp::code`].
+#[cfg(feature = "std")]
+fn spectre_unix_time() -> Result<u64> {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+    Ok(now.as_secs())
+}
+
+#[cfg(not(feature = "std"))]
+fn spectre_unix_time() -> Result<u64> {
+    Err(SpectreError::ClockUnavailable)
+}
+
+fn spectre_otp_decrypt(site_key: &[u8], state: &str) -> Result<SpectreOtp> {
+    Zeroizing::new(spectre_state_decrypt(site_key, state)?).parse()
+}
+
+/// Encrypt plaintext into a base64 state, keyed with the first 16 bytes of `key`
+fn spectre_state_encrypt(key: &[u8], plaintext: &str) -> Result<String> {
+    if plaintext.is_empty() {
+        return Err(SpectreError::EncryptionFailed);
     }
     
-    // Hex encode
-    let mut result = String::new();
-    for byte in encrypted {
-        write!(&mut result, "{:02x}", byte).unwrap();
-    }
+    let cipher = Aes128CbcEnc::new_from_slices(&key[..16], &[0u8; 16])
+        .map_err(|_| SpectreError::EncryptionFailed)?;
+    let encrypted = cipher.encrypt_padded_vec_mut::<Pkcs7>(plaintext.as_bytes());
     
-    Ok(result)
+    Ok(BASE64.encode(encrypted))
+}
+
+/// Decrypt a base64 state produced by [`spectre_site_state`]
+fn spectre_state_decrypt(key: &[u8], state: &str) -> Result<String> {
+    let encrypted = BASE64.decode(state.trim())
+        .map_err(|_| SpectreError::DecryptionFailed)?;
+    
+    let cipher = Aes128CbcDec::new_from_slices(&key[..16], &[0u8; 16])
+        .map_err(|_| SpectreError::DecryptionFailed)?;
+    let decrypted = cipher.decrypt_padded_vec_mut::<Pkcs7>(&encrypted)
+        .map_err(|_| SpectreError::DecryptionFailed)?;
+    
+    String::from_utf8(decrypted).map_err(|_| SpectreError::DecryptionFailed)
 }
 
 #[cfg(test)]
@@ -266,5 +464,13 @@ mod tests {
         );
         assert!(password.is_ok());
     }
+
+    #[test]
+    fn test_v0_template_seed() {
+        assert_eq!(template_seed(SpectreAlgorithm::V0, 0x00), 0x0000);
+        assert_eq!(template_seed(SpectreAlgorithm::V0, 0x7f), 0x7f00);
+        assert_eq!(template_seed(SpectreAlgorithm::V0, 0x80), 0x80ff);
+        assert_eq!(template_seed(SpectreAlgorithm::V1, 0x80), 0x80);
+    }
 }
 
@@ -0,0 +1,89 @@
+use alloc::{string::String, vec::Vec};
+use crate::algorithm::{spectre_site_result_keyed, spectre_user_key_mac, SpectreUserKey};
+use crate::error::Result;
+use crate::policy::SpectrePasswordPolicy;
+use crate::types::*;
+
+/// One site to generate in a batch
+///
+/// Defaults to the user's password for the site (authentication purpose,
+/// default counter, no parameter, context or policy); adjust with the `with_*` methods.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpectreSiteRequest<'a> {
+    pub site_name: &'a str,
+    pub result_type: SpectreResultType,
+    pub result_param: Option<&'a str>,
+    pub key_counter: SpectreCounter,
+    pub key_purpose: SpectreKeyPurpose,
+    pub key_context: Option<&'a str>,
+    /// Constraints for template results; other result types ignore it
+    pub policy: Option<&'a SpectrePasswordPolicy>,
+}
+
+impl<'a> SpectreSiteRequest<'a> {
+    pub fn new(site_name: &'a str, result_type: SpectreResultType) -> Self {
+        Self {
+            site_name,
+            result_type,
+            result_param: None,
+            key_counter: SPECTRE_COUNTER_DEFAULT,
+            key_purpose: SpectreKeyPurpose::Authentication,
+            key_context: None,
+            policy: None,
+        }
+    }
+
+    pub fn with_param(mut self, result_param: &'a str) -> Self {
+        self.result_param = Some(result_param);
+        self
+    }
+
+    pub fn with_counter(mut self, key_counter: SpectreCounter) -> Self {
+        self.key_counter = key_counter;
+        self
+    }
+
+    pub fn with_purpose(mut self, key_purpose: SpectreKeyPurpose, key_context: Option<&'a str>) -> Self {
+        self.key_purpose = key_purpose;
+        self.key_context = key_context;
+        self
+    }
+
+    pub fn with_policy(mut self, policy: &'a SpectrePasswordPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+}
+
+/// Generate results for many sites under one user key
+///
+/// The user key's HMAC key schedule is prepared once and reused for every
+/// site. Results come back in input order, each with its own error.
+pub fn spectre_site_results<'a>(
+    user_key: &SpectreUserKey,
+    requests: impl IntoIterator<Item = SpectreSiteRequest<'a>>,
+) -> Vec<Result<String>> {
+    let mac = spectre_user_key_mac(user_key);
+
+    requests
+        .into_iter()
+        .map(|request| spectre_site_result_keyed(user_key, &mac, &request))
+        .collect()
+}
+
+/// Like [`spectre_site_results`], spread across threads with rayon
+#[cfg(feature = "parallel")]
+pub fn spectre_site_results_parallel<'a>(
+    user_key: &SpectreUserKey,
+    requests: impl IntoIterator<Item = SpectreSiteRequest<'a>>,
+) -> Vec<Result<String>> {
+    use rayon::prelude::*;
+
+    let requests: Vec<_> = requests.into_iter().collect();
+    let mac = spectre_user_key_mac(user_key);
+
+    requests
+        .par_iter()
+        .map(|request| spectre_site_result_keyed(user_key, &mac, request))
+        .collect()
+}
@@ -1,6 +1,8 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use spectre::*;
 use spectre::util::cli::*;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::str::FromStr;
 
@@ -12,9 +14,16 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 #[command(version = VERSION)]
 #[command(about = "Stateless password management solution", long_about = None)]
 struct Args {
-    /// Site name for which to generate a password
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Site name for which to generate a password (URLs are reduced to their domain)
     site_name: Option<String>,
 
+    /// Use the site name exactly as typed, without reducing URLs to their domain
+    #[arg(long)]
+    exact_site: bool,
+
     /// User name (or -U to allow updating personal secret)
     #[arg(short = 'u', long, env = "SPECTRE_USERNAME")]
     user_name: Option<String>,
@@ -31,15 +40,23 @@ struct Args {
     #[arg(short = 'S', long)]
     secret: Option<String>,
 
-    /// Password template type
-    #[arg(short = 't', long, default_value = "long")]
-    result_type: String,
+    /// Password template type, mnemonic for BIP39 words, or custom:<template> (e.g. custom:Cvcvnnoo) [default: long, phrase for answers]
+    #[arg(short = 't', long)]
+    result_type: Option<String>,
 
-    /// Parameter value (login name, key bits, or personal password)
+    /// Parameter value (login name, key bits[:hex|base64], word count[:separator], or personal password)
     #[arg(short = 'P', long)]
     result_param: Option<String>,
 
-    /// Counter value
+    /// Site password policy, e.g. min=8,max=12,digit,no-seq,forbid=<> (empty to clear)
+    #[arg(long)]
+    policy: Option<String>,
+
+    /// Warn about password types with less entropy than this many bits
+    #[arg(long, env = "SPECTRE_ENTROPY_WARNING", default_value_t = SPECTRE_ENTROPY_WARNING_BITS)]
+    entropy_warning: f64,
+
+    /// Counter value for site passwords (logins and answers use 0)
     #[arg(short = 'c', long, default_value = "1")]
     counter: u32,
 
@@ -51,10 +68,38 @@ struct Args {
     #[arg(short = 'p', long, default_value = "auth")]
     purpose: String,
 
-    /// Purpose-specific context
+    /// Unicode normalization of names and secrets (nfc/nfkc/none; none for legacy users)
+    #[arg(long, env = "SPECTRE_NORMALIZATION")]
+    normalization: Option<String>,
+
+    /// Also print the site's login name, on the line before the password
+    #[arg(short = 'l', long)]
+    login: bool,
+
+    /// Set your standard login name, used for sites without their own (empty to clear)
+    #[arg(long)]
+    standard_login: Option<String>,
+
+    /// Purpose-specific context (for answers, the security question or its keyword)
     #[arg(short = 'C', long)]
     context: Option<String>,
 
+    /// List the site's security questions with their answers
+    #[arg(long)]
+    questions: bool,
+
+    /// Forget the site's security question given with -C
+    #[arg(long)]
+    forget_question: bool,
+
+    /// Write the site's SSH key pair to this file and <file>.pub, and print its fingerprint
+    #[arg(long, value_name = "FILE")]
+    ssh_key: Option<PathBuf>,
+
+    /// Protect the SSH private key with a passphrase (prompted for)
+    #[arg(long, requires = "ssh_key")]
+    ssh_passphrase: bool,
+
     /// File format (with fallback)
     #[arg(short = 'f', long, env = "SPECTRE_FORMAT")]
     format: Option<String>,
@@ -63,7 +108,7 @@ struct Args {
     #[arg(short = 'F', long)]
     format_fixed: Option<String>,
 
-    /// Save file in redacted format
+    /// Save file in redacted format (0 saves passwords, logins and answers in plaintext)
     #[arg(short = 'R', long, default_value = "1")]
     redacted: String,
 
@@ -80,16 +125,35 @@ struct Args {
     no_newline: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the built-in known-answer vectors against this binary
+    Selftest,
+}
+
 struct Operation {
     user_name: String,
-    user_secret: String,
+    user_secret: SpectreSecret,
     site_name: String,
+    exact_site: bool,
     result_type: SpectreResultType,
+    explicit_type: bool,
     result_param: Option<String>,
+    template: Option<SpectreTemplate>,
+    policy: Option<Option<SpectrePasswordPolicy>>,
+    entropy_warning: f64,
     counter: SpectreCounter,
-    algorithm: SpectreAlgorithm,
+    algorithm: Option<SpectreAlgorithm>,
+    normalization: Option<SpectreNormalization>,
     purpose: SpectreKeyPurpose,
     context: Option<String>,
+    question: Option<String>,
+    print_login: bool,
+    standard_login: Option<SpectreSecret>,
+    list_questions: bool,
+    forget_question: bool,
+    ssh_key: Option<PathBuf>,
+    ssh_passphrase: bool,
     format: SpectreFormat,
     redacted: bool,
     no_newline: bool,
@@ -110,6 +174,10 @@ fn run(args: Args) -> Result<()> {
     // Calculate verbosity level
     let verbosity = args.verbose as i8 - args.quiet as i8;
     
+    if let Some(Command::Selftest) = args.command {
+        return selftest(verbosity);
+    }
+    
     // Determine if we're allowing password updates
     let (user_name_arg, allow_password_update) = if let Some(name) = args.user_name_update {
         (Some(name), true)
@@ -129,7 +197,7 @@ fn run(args: Args) -> Result<()> {
     }
     
     // Get user secret
-    let mut user_secret = if let Some(secret) = args.secret {
+    let user_secret = SpectreSecret::new(if let Some(secret) = args.secret {
         if verbosity >= 0 {
             eprintln!("Warning: Passing secrets via command-line is insecure!");
         }
@@ -138,7 +206,7 @@ fn run(args: Args) -> Result<()> {
         read_fd(fd).map_err(SpectreError::Io)?
     } else {
         prompt_password("Your personal secret: ")?
-    };
+    });
     
     if user_secret.is_empty() {
         return Err(SpectreError::MissingField("personal secret".to_string()));
@@ -155,11 +223,35 @@ fn run(args: Args) -> Result<()> {
         return Err(SpectreError::MissingField("site name".to_string()));
     }
     
-    // Parse result type
-    let result_type = SpectreResultType::from_str(&args.result_type)?;
+    // Parse key purpose; security questions are managed as answers
+    let purpose = if args.questions || args.forget_question {
+        SpectreKeyPurpose::Recovery
+    } else {
+        SpectreKeyPurpose::from_str(&args.purpose)?
+    };
+    
+    // Parse result type; custom types carry their template as custom:<template>
+    let explicit_type = args.result_type.is_some();
+    let (result_type, template) = match args.result_type.as_deref().map(|t| (t, t.split_once(':'))) {
+        Some((_, Some((kind, template)))) => {
+            let result_type = SpectreResultType::from_str(kind)?;
+            if !result_type.is_custom() {
+                return Err(SpectreError::InvalidResultType(args.result_type.unwrap_or_default()));
+            }
+            (result_type, Some(SpectreTemplate::from_str(template)?))
+        }
+        Some((result_type, None)) => (SpectreResultType::from_str(result_type)?, None),
+        None if purpose == SpectreKeyPurpose::Recovery => (SPECTRE_RESULT_DEFAULT_ANSWER, None),
+        // Resolved from the site's login, the standard login or the login default
+        None if purpose == SpectreKeyPurpose::Identification => (SpectreResultType::None, None),
+        None => (SPECTRE_RESULT_DEFAULT_RESULT, None),
+    };
     
-    // Parse key purpose
-    let purpose = SpectreKeyPurpose::from_str(&args.purpose)?;
+    // Parse password policy; an empty policy clears the site's
+    let policy = args.policy.as_deref()
+        .map(|rules| SpectrePasswordPolicy::from_str(rules)
+            .map(|policy| (policy != SpectrePasswordPolicy::default()).then_some(policy)))
+        .transpose()?;
     
     // Determine file format
     let format_str = args.format_fixed.as_ref().or(args.format.as_ref());
@@ -173,25 +265,46 @@ fn run(args: Args) -> Result<()> {
     // Parse redacted flag
     let redacted = parse_bool(&args.redacted);
     
-    // Determine algorithm version
-    let algorithm = args.algorithm.unwrap_or(SPECTRE_ALGORITHM_CURRENT);
-    if !(SPECTRE_ALGORITHM_FIRST..=SPECTRE_ALGORITHM_LAST).contains(&algorithm) {
-        return Err(SpectreError::InvalidAlgorithm(algorithm));
-    }
+    // Determine algorithm version (existing sites keep their own unless overridden)
+    let algorithm = args.algorithm.map(SpectreAlgorithm::try_from).transpose()?;
+    
+    // Determine normalization (existing users keep their own unless overridden)
+    let normalization = args.normalization.as_deref().map(SpectreNormalization::from_str).transpose()?;
     
     // Counter is always valid since it's u32 and range is 0..u32::MAX
     // No validation needed
     
+    // Logins and answers use the initial counter, as upstream's clients do, and
+    // one-time code seeds the default one; none follow the site's password counter
+    let counter = match purpose {
+        SpectreKeyPurpose::Identification | SpectreKeyPurpose::Recovery => SPECTRE_COUNTER_INITIAL,
+        _ if result_type.is_one_time_code() => SPECTRE_COUNTER_DEFAULT,
+        _ => args.counter,
+    };
+    
     let mut operation = Operation {
         user_name,
-        user_secret: user_secret.clone(),
+        user_secret,
         site_name,
+        exact_site: args.exact_site,
         result_type,
+        explicit_type,
         result_param: args.result_param,
-        counter: args.counter,
+        template,
+        policy,
+        entropy_warning: args.entropy_warning,
+        counter,
         algorithm,
+        normalization,
         purpose,
         context: args.context,
+        question: None,
+        print_login: args.login,
+        standard_login: args.standard_login.map(SpectreSecret::new),
+        list_questions: args.questions,
+        forget_question: args.forget_question,
+        ssh_key: args.ssh_key,
+        ssh_passphrase: args.ssh_passphrase,
         format,
         redacted,
         no_newline: args.no_newline,
@@ -199,106 +312,204 @@ fn run(args: Args) -> Result<()> {
         verbosity,
     };
     
-    // Execute the operation
-    execute_operation(&mut operation)?;
-    
-    // Zero out sensitive data
-    zero_string(&mut user_secret);
-    zero_string(&mut operation.user_secret);
-    
-    Ok(())
+    // Execute the operation; the secret and keys are wiped as they're dropped
+    execute_operation(&mut operation)
 }
 
 fn execute_operation(op: &mut Operation) -> Result<()> {
-    // Derive user key
-    let user_key = spectre_user_key(&op.user_name, &op.user_secret, op.algorithm)?;
-    
-    // Generate identicon
-    let identicon = spectre_identicon(&op.user_name, &op.user_secret)?;
-    let identicon_render = spectre_identicon_render(identicon);
-    
-    // Load or create user file
+    // Load the user file first, so the user key is derived once with its settings
     let file_path = if op.format != SpectreFormat::None {
         spectre_user_path(&op.user_name, op.format)
     } else {
         None
     };
+    let saved_user = match file_path.as_ref().map(spectre_marshal_read) {
+        Some(Ok((_, user))) => user,
+        _ => None,
+    };
     
-    let mut user = if let Some(ref path) = file_path {
-        match spectre_marshal_read(path) {
-            Ok((_, Some(mut user))) => {
-                // Authenticate user
-                if let Err(SpectreError::UserSecretMismatch) = spectre_marshal_auth(&mut user, &op.user_secret) {
-                    if op.allow_password_update {
-                        eprintln!("Personal secret mismatch. Please confirm old secret to update.");
-                        let old_secret = prompt_password("Old personal secret: ")?;
-                        spectre_marshal_auth(&mut user, &old_secret)?;
-                        
-                        // Update to new secret
-                        let new_key = spectre_user_key(&op.user_name, &op.user_secret, op.algorithm)?;
-                        user.key_id = new_key.key_id;
-                        user.identicon = identicon;
-                    } else {
-                        return Err(SpectreError::UserSecretMismatch);
-                    }
-                }
-                user
-            }
-            _ => {
-                // Create new user
-                SpectreMarshalledUser::new(
-                    op.user_name.clone(),
-                    identicon,
-                    user_key.key_id,
-                    op.algorithm,
-                )
+    let (mut user, user_key) = if let Some(mut user) = saved_user {
+        if let Some(normalization) = op.normalization {
+            user.normalization = normalization;
+        }
+        let user_key = derive_user_key(op, user.algorithm, user.normalization)?;
+        
+        // Authenticate user
+        match spectre_marshal_unlock(&mut user, &op.user_secret, &user_key) {
+            Err(SpectreError::UserSecretMismatch) if op.allow_password_update => {
+                eprintln!("Personal secret mismatch. Please confirm old secret to update.");
+                let old_secret = SpectreSecret::new(prompt_password("Old personal secret: ")?);
+                spectre_marshal_auth(&mut user, &old_secret)?;
+                
+                // Update to new secret
+                user.key_id = user_key.key_id;
+                user.identicon = spectre_identicon_normalized(&op.user_name, &op.user_secret, user.normalization);
             }
+            result => result?,
         }
+        (user, user_key)
     } else {
-        // No file format, create ephemeral user
-        SpectreMarshalledUser::new(
+        // Create a new user, saved if there's a file format
+        let algorithm = op.algorithm.unwrap_or(SPECTRE_ALGORITHM_CURRENT);
+        let normalization = op.normalization.unwrap_or_default();
+        let user_key = derive_user_key(op, algorithm, normalization)?;
+        let mut user = SpectreMarshalledUser::new(
             op.user_name.clone(),
-            identicon,
+            spectre_identicon_normalized(&op.user_name, &op.user_secret, normalization),
             user_key.key_id,
-            op.algorithm,
-        )
+            algorithm,
+        );
+        user.normalization = normalization;
+        (user, user_key)
     };
     
     // Update redacted setting
     user.redacted = op.redacted;
     
+    if let Some(login) = op.standard_login.take() {
+        spectre_user_set_login(&user_key, &mut user, (!login.is_empty()).then_some(&login))?;
+        if op.verbosity >= 1 {
+            eprintln!("Standard login: {}", if login.is_empty() { "(generated)" } else { &login });
+        }
+    }
+    
+    // Reduce pasted URLs to the registrable domain, unless a site is already saved under the name as typed
+    let mut site_url = None;
+    if !op.exact_site && user.find_site(&op.site_name).is_none()
+        && let Ok(site_name) = spectre_site_name(&op.site_name)
+        && site_name != op.site_name {
+            if op.verbosity >= 1 {
+                eprintln!("Site: {} (from {})", site_name, op.site_name);
+            }
+            site_url = Some(std::mem::replace(&mut op.site_name, site_name));
+        }
+    
     // Find or create site
     let mut site = if let Some(existing_site) = user.find_site(&op.site_name).cloned() {
         existing_site
     } else {
+        let (result_type, counter) = if op.purpose == SpectreKeyPurpose::Authentication && !op.result_type.is_one_time_code() {
+            (op.result_type, op.counter)
+        } else {
+            (SPECTRE_RESULT_DEFAULT_RESULT, SPECTRE_COUNTER_DEFAULT)
+        };
         SpectreMarshalledSite::new(
             op.site_name.clone(),
-            op.result_type,
-            op.counter,
-            op.algorithm,
+            result_type,
+            counter,
+            user.algorithm,
         )
     };
     
+    if site_url.is_some() {
+        site.url = site_url;
+    }
+    
+    // Sites remember the algorithm they were created with
+    if let Some(algorithm) = op.algorithm {
+        site.algorithm = algorithm;
+    }
+    let site_key = if user_key.covers_algorithm(&user.user_name, site.algorithm) {
+        let mut site_key = user_key.clone();
+        site_key.algorithm = site.algorithm;
+        site_key
+    } else {
+        derive_user_key(op, site.algorithm, user.normalization)?
+    };
+    
+    if op.list_questions {
+        return list_questions(&site_key, &site);
+    }
+    
+    if let Some(path) = op.ssh_key.clone() {
+        write_ssh_key(op, &site_key, &path)?;
+        site.uses += 1;
+        site.last_used = chrono::Utc::now();
+        user.last_used = chrono::Utc::now();
+        user.add_site(site);
+        return save_user(op, file_path.as_ref(), &user, &[&user_key, &site_key]);
+    }
+    
+    // Answers are stored by keyword; a full question resolves to upstream's keyword for it
+    if op.purpose == SpectreKeyPurpose::Recovery
+        && let Some(context) = op.context.clone()
+        && site.find_question(&context).is_none() {
+            let keyword = spectre_question_keyword(&context);
+            if keyword != context {
+                if op.verbosity >= 1 {
+                    eprintln!("Keyword: {}", keyword);
+                }
+                // Another question saved under this keyword would share its answer
+                if let Some(saved) = site.find_question(&keyword).and_then(|q| q.text.as_deref())
+                    && saved != context {
+                        return Err(SpectreError::QuestionConflict(format!(
+                            "\"{}\" has the same keyword (\"{}\") as \"{}\"; forget that one with --forget-question, \
+                             or give this one a keyword of its own with -C <keyword>",
+                            context, keyword, saved
+                        )));
+                    }
+                op.question = Some(context);
+                op.context = Some(keyword);
+            }
+        }
+    let keyword = op.context.clone().unwrap_or_default();
+    
+    if op.forget_question {
+        let question = site.remove_question(&keyword).ok_or_else(|| SpectreError::MissingField(
+            format!("security question \"{}\" for {}", keyword, site.site_name)
+        ))?;
+        if op.verbosity >= 0 {
+            eprintln!("Forgot \"{}\" for {}", question.text.as_deref().unwrap_or(&keyword), site.site_name);
+        }
+        user.add_site(site);
+        return save_user(op, file_path.as_ref(), &user, &[&user_key, &site_key]);
+    }
+    
+    // Saved questions keep their answer type unless another is asked for
+    if op.purpose == SpectreKeyPurpose::Recovery && !op.explicit_type
+        && let Some(question) = site.find_question(&keyword) {
+            op.result_type = question.question_type;
+        }
+    
     // Update site settings if provided
     if op.result_type != SpectreResultType::None {
         match op.purpose {
+            // One-time codes come on top of the site's password and leave it as it is
+            SpectreKeyPurpose::Authentication if op.result_type.is_one_time_code() => {}
             SpectreKeyPurpose::Authentication => {
                 site.result_type = op.result_type;
                 site.counter = op.counter;
+                if op.template.is_some() {
+                    site.result_template = op.template.clone();
+                }
+                if let (Some(_), Some(param)) = (op.result_type.word_list(), op.result_param.as_deref()) {
+                    site.result_words = Some(SpectreWordsParam::from_str(param)?);
+                }
+                if let Some(policy) = op.policy.take() {
+                    site.policy = policy;
+                }
             }
             SpectreKeyPurpose::Identification => {
                 site.login_type = op.result_type;
+                if op.template.is_some() {
+                    site.login_template = op.template.clone();
+                }
             }
             SpectreKeyPurpose::Recovery => {
                 // Handle recovery question
-                let keyword = op.context.as_deref().unwrap_or("");
-                let mut question = if let Some(q) = site.find_question(keyword).cloned() {
+                let mut question = if let Some(q) = site.find_question(&keyword).cloned() {
                     q
                 } else {
-                    SpectreMarshalledQuestion::new(keyword.to_string(), op.result_type)
+                    SpectreMarshalledQuestion::new(keyword.clone(), op.result_type)
                 };
                 question.question_type = op.result_type;
-                site.add_question(question);
+                if op.question.is_some() {
+                    question.text = op.question.clone();
+                }
+                if op.template.is_some() {
+                    question.template = op.template.clone();
+                }
+                site.add_question(question)?;
             }
         }
     }
@@ -307,58 +518,137 @@ fn execute_operation(op: &mut Operation) -> Result<()> {
     if op.verbosity >= 0 {
         eprintln!("{}'s {} for {}:", user.user_name, 
                   match op.purpose {
+                      _ if op.result_type.is_one_time_code() => "one-time code",
                       SpectreKeyPurpose::Authentication => "password",
                       SpectreKeyPurpose::Identification => "login",
                       SpectreKeyPurpose::Recovery => "answer",
                   },
                   site.site_name);
+        let identicon_render = if std::io::stderr().is_terminal() {
+            user.identicon.render_ansi()
+        } else {
+            user.identicon.render_text()
+        };
         eprintln!("[ {} ]", identicon_render);
     }
     
     // Handle stateful result types (encrypt if needed)
     if op.result_type.is_stateful() && op.result_param.is_some() {
-        let plaintext = op.result_param.as_ref().unwrap();
+        let plaintext = SpectreSecret::new(op.result_param.take().unwrap());
         let state = spectre_site_state(
-            &user_key,
+            &site_key,
             &op.site_name,
             op.result_type,
-            plaintext,
+            &plaintext,
             op.counter,
             op.purpose,
             op.context.as_deref(),
         )?;
         
-        match op.purpose {
-            SpectreKeyPurpose::Authentication => {
-                site.result_state = Some(state.clone());
-            }
-            SpectreKeyPurpose::Identification => {
-                site.login_state = Some(state.clone());
+        store_state(&mut site, op.result_type, op.purpose, op.context.as_deref(), state.clone());
+        op.result_param = Some(state);
+    } else if op.result_type.is_stateful() {
+        // Decrypt the state stored by an earlier run
+        op.result_param = match op.purpose {
+            _ if op.result_type.is_one_time_code() => site.otp_state.clone(),
+            SpectreKeyPurpose::Authentication => site.result_state.clone(),
+            SpectreKeyPurpose::Identification => site.login_state.clone(),
+            SpectreKeyPurpose::Recovery => {
+                let keyword = op.context.as_deref().unwrap_or("");
+                site.find_question(keyword).and_then(|q| q.state.clone())
             }
+        };
+        if op.result_param.is_none() {
+            return Err(SpectreError::MissingField(format!(
+                "{} state for {} (set it with -P)", op.result_type.short_name(), op.site_name
+            )));
+        }
+    } else if op.result_type.is_custom() {
+        // The template saved for this site and purpose (updated above from -t custom:<template>)
+        let template = match op.purpose {
+            SpectreKeyPurpose::Authentication => site.result_template.as_ref(),
+            SpectreKeyPurpose::Identification => site.login_template.as_ref(),
             SpectreKeyPurpose::Recovery => {
                 let keyword = op.context.as_deref().unwrap_or("");
-                if let Some(question) = site.find_question_mut(keyword) {
-                    question.state = Some(state.clone());
-                }
+                site.find_question(keyword).and_then(|q| q.template.as_ref())
             }
+        };
+        op.result_param = match template {
+            Some(template) => Some(template.to_string()),
+            None => return Err(SpectreError::MissingField(format!(
+                "template for {} (set it with -t custom:<template>)", op.site_name
+            ))),
+        };
+    } else if op.result_type.word_list().is_some() && op.purpose == SpectreKeyPurpose::Authentication {
+        // The word count and separator saved for this site (updated above from -P)
+        op.result_param = site.result_words.as_ref().map(ToString::to_string);
+    }
+    
+    // Estimate how hard template results are to guess
+    let entropy = if op.result_type.is_custom() {
+        op.result_param.as_deref()
+            .and_then(|template| SpectreTemplate::from_str(template).ok())
+            .map(|template| template.entropy())
+    } else if let (Some(list), Some(param)) = (op.result_type.word_list(), op.result_param.as_deref()) {
+        SpectreWordsParam::from_str(param).ok()
+            .map(|param| list.entropy(param.count))
+    } else {
+        op.result_type.entropy()
+    };
+    if let Some(bits) = entropy {
+        if op.verbosity >= 1 {
+            eprintln!("Entropy: {:.1} bits ({})", bits, SpectreStrength::from_entropy(bits).name());
+        }
+        if op.verbosity >= 0 && op.purpose == SpectreKeyPurpose::Authentication && bits < op.entropy_warning {
+            eprintln!(
+                "Warning: {} passwords have only {:.1} bits of entropy (below {})",
+                op.result_type.short_name(), bits, op.entropy_warning
+            );
+        }
+    }
+    
+    // Generate result; the site's policy governs its passwords
+    let mut request = SpectreSiteRequest::new(&op.site_name, op.result_type)
+        .with_counter(op.counter)
+        .with_purpose(op.purpose, op.context.as_deref());
+    request.result_param = op.result_param.as_deref();
+    if let (SpectreKeyPurpose::Authentication, Some(policy)) = (op.purpose, &site.policy) {
+        request = request.with_policy(policy);
+        if op.verbosity >= 1 {
+            eprintln!("Policy: {}", policy);
+        }
+    }
+    let result = SpectreSecret::new(match (op.purpose, op.result_type) {
+        (SpectreKeyPurpose::Identification, SpectreResultType::None) => spectre_site_login(&site_key, &user, &site)?,
+        _ => spectre_site_request_result(&site_key, &request)?,
+    });
+    
+    // A counter-based code is used up once shown; store the seed at its next counter
+    if op.result_type.is_one_time_code() {
+        let mut otp = spectre_site_otp(&site_key, &request)?;
+        if op.verbosity >= 1 {
+            eprintln!("One-time code: {}, {} digits, {}", otp.kind, otp.digits, otp.algorithm.name());
+        }
+        if let SpectreOtpKind::Hotp { .. } = otp.kind {
+            otp.advance();
+            let state = spectre_site_state(
+                &site_key,
+                &op.site_name,
+                op.result_type,
+                &SpectreSecret::new(otp.uri()),
+                op.counter,
+                op.purpose,
+                op.context.as_deref(),
+            )?;
+            store_state(&mut site, op.result_type, op.purpose, op.context.as_deref(), state);
         }
-        
-        op.result_param = Some(state);
     }
     
-    // Generate result
-    let result = spectre_site_result(
-        &user_key,
-        &op.site_name,
-        op.result_type,
-        op.result_param.as_deref(),
-        op.counter,
-        op.purpose,
-        op.context.as_deref(),
-    )?;
-    
-    // Output result
-    print!("{}", result);
+    // Output result, after the login if it was asked for
+    if op.print_login && op.purpose == SpectreKeyPurpose::Authentication {
+        println!("{}", SpectreSecret::new(spectre_site_login(&site_key, &user, &site)?).as_str());
+    }
+    print!("{}", result.as_str());
     if !op.no_newline {
         println!();
     }
@@ -371,10 +661,77 @@ fn execute_operation(op: &mut Operation) -> Result<()> {
     // Save site back to user
     user.add_site(site);
     
-    // Save user file if format is not None
+    save_user(op, file_path.as_ref(), &user, &[&user_key, &site_key])
+}
+
+/// Write the site's SSH key pair, then print its fingerprint
+fn write_ssh_key(op: &Operation, user_key: &SpectreUserKey, path: &Path) -> Result<()> {
+    let request = SpectreSiteRequest::new(&op.site_name, SpectreResultType::None)
+        .with_counter(op.counter)
+        .with_purpose(op.purpose, op.context.as_deref());
+    let ssh_key = spectre_site_ssh_key(user_key, &request, &op.site_name)?;
+
+    let passphrase = if op.ssh_passphrase {
+        let passphrase = SpectreSecret::new(prompt_password("SSH key passphrase: ")?);
+        if passphrase.as_str() != prompt_password("Repeat passphrase: ")? {
+            return Err(SpectreError::SshKey("passphrases don't match".to_string()));
+        }
+        Some(passphrase)
+    } else {
+        None
+    };
+    let private_key = ssh_key.private_key(passphrase.as_deref())?;
+
+    let mut public_path = path.as_os_str().to_owned();
+    public_path.push(".pub");
+    write_private_file(path, private_key.as_bytes())?;
+    std::fs::write(&public_path, format!("{}\n", ssh_key.public_key()))?;
+
+    if op.verbosity >= 0 {
+        eprintln!("{}'s SSH key for {}:", op.user_name, op.site_name);
+        eprintln!("Wrote {} and {}", path.display(), PathBuf::from(&public_path).display());
+    }
+    print!("{}", ssh_key.fingerprint());
+    if !op.no_newline {
+        println!();
+    }
+    Ok(())
+}
+
+/// Write a file only its owner can read, as ssh requires of private keys
+fn write_private_file(path: &Path, contents: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // An existing file keeps its mode on open; tighten it before writing
+        if path.exists() {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(path)?.write_all(contents)?;
+    Ok(())
+}
+
+/// Save the user file, if there is one; unredacted files are unlocked with the user keys at hand
+///
+/// A non-ASCII user name needs another key for sites whose algorithm counts
+/// its length differently; those are derived as the file needs them.
+fn save_user(op: &Operation, file_path: Option<&PathBuf>, user: &SpectreMarshalledUser, user_keys: &[&SpectreUserKey]) -> Result<()> {
     if let Some(path) = file_path
         && op.format != SpectreFormat::None {
-            spectre_marshal_write(&path, op.format, &user)?;
+            let mut user_keys: Vec<_> = user_keys.iter().map(|&user_key| user_key.clone()).collect();
+            let algorithms = std::iter::once(user.algorithm).chain(user.sites.iter().map(|site| site.algorithm));
+            for algorithm in algorithms.filter(|_| !user.redacted) {
+                if !user_keys.iter().any(|user_key| user_key.covers_algorithm(&user.user_name, algorithm)) {
+                    user_keys.push(derive_user_key(op, algorithm, user.normalization)?);
+                }
+            }
+            spectre_marshal_write(path, op.format, user, &user_keys)?;
             if op.verbosity >= 1 {
                 eprintln!("Saved to: {}", path.display());
             }
@@ -382,3 +739,110 @@ fn execute_operation(op: &mut Operation) -> Result<()> {
     
     Ok(())
 }
+
+/// Print each of the site's security questions with its answer
+fn list_questions(user_key: &SpectreUserKey, site: &SpectreMarshalledSite) -> Result<()> {
+    if site.questions.is_empty() {
+        eprintln!("No security questions saved for {}", site.site_name);
+        return Ok(());
+    }
+    
+    let requests = site.questions.iter().map(|question| {
+        let mut request = SpectreSiteRequest::new(&site.site_name, question.question_type)
+            .with_counter(SPECTRE_COUNTER_INITIAL)
+            .with_purpose(SpectreKeyPurpose::Recovery, Some(&question.keyword));
+        request.result_param = match &question.template {
+            Some(template) if question.question_type.is_custom() => Some(template.as_str()),
+            _ => question.state.as_deref(),
+        };
+        request
+    });
+    
+    for (question, answer) in site.questions.iter().zip(spectre_site_results(user_key, requests)) {
+        let label = match (&question.text, question.keyword.as_str()) {
+            (Some(text), _) => text.as_str(),
+            (None, "") => "(any question)",
+            (None, keyword) => keyword,
+        };
+        match answer {
+            Ok(answer) => println!("{}: {}", label, SpectreSecret::new(answer).as_str()),
+            Err(e) => eprintln!("{}: {}", label, e),
+        }
+    }
+    
+    Ok(())
+}
+
+/// Save an encrypted state with the site, for the result type and purpose it was made for
+fn store_state(
+    site: &mut SpectreMarshalledSite,
+    result_type: SpectreResultType,
+    purpose: SpectreKeyPurpose,
+    context: Option<&str>,
+    state: String,
+) {
+    match purpose {
+        _ if result_type.is_one_time_code() => {
+            site.otp_state = Some(state);
+        }
+        SpectreKeyPurpose::Authentication => {
+            site.result_state = Some(state);
+        }
+        SpectreKeyPurpose::Identification => {
+            site.login_state = Some(state);
+        }
+        SpectreKeyPurpose::Recovery => {
+            let keyword = context.unwrap_or("");
+            if let Some(question) = site.find_question_mut(keyword) {
+                question.state = Some(state);
+            }
+        }
+    }
+}
+
+/// Derive the user key, showing a progress indicator on interactive terminals
+fn derive_user_key(
+    op: &Operation,
+    algorithm: SpectreAlgorithm,
+    normalization: SpectreNormalization,
+) -> Result<SpectreUserKey> {
+    if op.verbosity < 0 || !std::io::stderr().is_terminal() {
+        return spectre_user_key_normalized(&op.user_name, &op.user_secret, algorithm, normalization);
+    }
+    
+    let mut derivation = SpectreUserKeyDerivation::normalized(&op.user_name, &op.user_secret, algorithm, normalization);
+    while !derivation.step(SPECTRE_DERIVATION_STEP) {
+        eprint!("\rDeriving user key... {:3.0}%", derivation.progress() * 100.0);
+    }
+    // Clear the indicator line
+    eprint!("\r\x1b[2K");
+    
+    derivation.finish()
+}
+
+fn selftest(verbosity: i8) -> Result<()> {
+    let outcomes = spectre_selftest();
+    let failed = outcomes.iter().filter(|outcome| !outcome.passed()).count();
+    
+    for outcome in &outcomes {
+        let vector = outcome.vector;
+        match &outcome.result {
+            Ok(result) if outcome.passed() => {
+                if verbosity >= 1 {
+                    eprintln!("✓ {}: {}", vector.id, result);
+                }
+            }
+            Ok(result) => eprintln!("✗ {}: expected {}, got {}", vector.id, vector.result, result),
+            Err(e) => eprintln!("✗ {}: {}", vector.id, e),
+        }
+    }
+    
+    if failed > 0 {
+        return Err(SpectreError::SelfTestFailed(failed));
+    }
+    if verbosity >= 0 {
+        eprintln!("Self-test passed: {} vectors", outcomes.len());
+    }
+    
+    Ok(())
+}
@@ -0,0 +1,323 @@
+use alloc::{vec, vec::Vec};
+use core::future::Future;
+use core::pin::Pin;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+use pbkdf2::pbkdf2_hmac;
+use sha2::Sha256;
+use zeroize::{Zeroize, Zeroizing};
+use crate::algorithm::{
+    spectre_user_key_salt, SpectreUserKey, SPECTRE_DK_LEN, SPECTRE_N, SPECTRE_P, SPECTRE_R,
+};
+use crate::error::{Result, SpectreError};
+use crate::secret::SpectreSecretBytes;
+use crate::types::{SpectreAlgorithm, SpectreNormalization};
+
+/// Words in one scrypt block (128 * r bytes)
+const BLOCK_WORDS: usize = 32 * SPECTRE_R as usize;
+
+/// ROMix iterations between progress reports and cancellation checks
+pub const SPECTRE_DERIVATION_STEP: u32 = 1024;
+
+/// A shared flag to abandon a user key derivation that is no longer needed
+///
+/// Clones share the same flag, so one clone can be handed to the derivation
+/// while another is kept to cancel it.
+#[derive(Debug, Clone, Default)]
+pub struct SpectreCancelToken(Arc<AtomicBool>);
+
+impl SpectreCancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A user key derivation that runs in caller-sized steps
+///
+/// This is the same scrypt as [`spectre_user_key`](crate::spectre_user_key),
+/// with the ROMix loop unrolled into resumable state. Drive it with
+/// [`step`](Self::step) until it's done, then [`finish`](Self::finish) it.
+/// Intermediate state is wiped when the derivation is dropped.
+pub struct SpectreUserKeyDerivation {
+    algorithm: SpectreAlgorithm,
+    normalization: SpectreNormalization,
+    secret: SpectreSecretBytes,
+    blocks: Vec<u32>,
+    x: Vec<u32>,
+    v: Vec<u32>,
+    scratch: Vec<u32>,
+    block: usize,
+    iteration: u32,
+}
+
+impl SpectreUserKeyDerivation {
+    /// Start a derivation with the name and secret normalized to NFC
+    pub fn new(user_name: &str, user_secret: &str, algorithm: SpectreAlgorithm) -> Self {
+        Self::normalized(user_name, user_secret, algorithm, SpectreNormalization::default())
+    }
+
+    /// Start a derivation, normalizing the name and secret as given
+    pub fn normalized(
+        user_name: &str,
+        user_secret: &str,
+        algorithm: SpectreAlgorithm,
+        normalization: SpectreNormalization,
+    ) -> Self {
+        let salt = spectre_user_key_salt(&normalization.apply(user_name), algorithm);
+        let secret = SpectreSecretBytes::new(normalization.apply(user_secret).into_owned().into_bytes());
+
+        let mut bytes = Zeroizing::new(vec![0u8; SPECTRE_P as usize * BLOCK_WORDS * 4]);
+        pbkdf2_hmac::<Sha256>(&secret, &salt, 1, &mut bytes);
+        let blocks: Vec<u32> = bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+
+        Self {
+            algorithm,
+            normalization,
+            secret,
+            x: blocks[..BLOCK_WORDS].to_vec(),
+            blocks,
+            v: vec![0; SPECTRE_N as usize * BLOCK_WORDS],
+            scratch: vec![0; BLOCK_WORDS],
+            block: 0,
+            iteration: 0,
+        }
+    }
+
+    /// Fraction of the work done, from 0.0 to 1.0
+    pub fn progress(&self) -> f32 {
+        let per_block = 2 * SPECTRE_N as u64;
+        let done = self.block as u64 * per_block + self.iteration as u64;
+        done as f32 / (SPECTRE_P as u64 * per_block) as f32
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.block == SPECTRE_P as usize
+    }
+
+    /// Run up to `iterations` ROMix iterations; returns whether the derivation is done
+    pub fn step(&mut self, iterations: u32) -> bool {
+        let n = SPECTRE_N as usize;
+
+        for _ in 0..iterations {
+            if self.is_done() {
+                break;
+            }
+
+            let i = self.iteration as usize;
+            if i < n {
+                self.v[i * BLOCK_WORDS..(i + 1) * BLOCK_WORDS].copy_from_slice(&self.x);
+            } else {
+                // Integerify: the first word of the last 64-byte sub-block, mod N
+                let j = self.x[BLOCK_WORDS - 16] as usize & (n - 1);
+                let v = &self.v[j * BLOCK_WORDS..(j + 1) * BLOCK_WORDS];
+                self.x.iter_mut().zip(v).for_each(|(x, v)| *x ^= v);
+            }
+            block_mix(&mut self.x, &mut self.scratch);
+
+            self.iteration += 1;
+            if self.iteration as usize == 2 * n {
+                let start = self.block * BLOCK_WORDS;
+                self.blocks[start..start + BLOCK_WORDS].copy_from_slice(&self.x);
+                self.block += 1;
+                self.iteration = 0;
+
+                if !self.is_done() {
+                    let start = self.block * BLOCK_WORDS;
+                    self.x.copy_from_slice(&self.blocks[start..start + BLOCK_WORDS]);
+                }
+            }
+        }
+
+        self.is_done()
+    }
+
+    /// Produce the user key once every step has run
+    pub fn finish(self) -> Result<SpectreUserKey> {
+        if !self.is_done() {
+            return Err(SpectreError::KeyDerivationFailed);
+        }
+
+        let bytes: Zeroizing<Vec<u8>> = Zeroizing::new(
+            self.blocks.iter().flat_map(|word| word.to_le_bytes()).collect()
+        );
+        let mut key_data = SpectreSecretBytes::new(vec![0u8; SPECTRE_DK_LEN]);
+        pbkdf2_hmac::<Sha256>(&self.secret, &bytes, 1, key_data.as_mut_bytes());
+
+        Ok(SpectreUserKey::from_key_data(key_data, self.algorithm, self.normalization))
+    }
+}
+
+impl Drop for SpectreUserKeyDerivation {
+    fn drop(&mut self) {
+        self.blocks.zeroize();
+        self.x.zeroize();
+        self.v.zeroize();
+        self.scratch.zeroize();
+    }
+}
+
+/// Derive a user key, reporting progress and checking for cancellation as it goes
+///
+/// The name and secret are normalized as given, like
+/// [`spectre_user_key_normalized`](crate::spectre_user_key_normalized).
+/// `progress` receives the fraction done after every step. Returns
+/// [`SpectreError::Cancelled`] as soon as `cancel` is triggered.
+pub fn spectre_user_key_with_progress(
+    user_name: &str,
+    user_secret: &str,
+    algorithm: SpectreAlgorithm,
+    normalization: SpectreNormalization,
+    mut progress: impl FnMut(f32),
+    cancel: &SpectreCancelToken,
+) -> Result<SpectreUserKey> {
+    let mut derivation = SpectreUserKeyDerivation::normalized(user_name, user_secret, algorithm, normalization);
+
+    loop {
+        if cancel.is_cancelled() {
+            return Err(SpectreError::Cancelled);
+        }
+        let done = derivation.step(SPECTRE_DERIVATION_STEP);
+        progress(derivation.progress());
+        if done {
+            return derivation.finish();
+        }
+    }
+}
+
+/// Derive a user key without blocking the executor
+///
+/// Like [`spectre_user_key_with_progress`], but yields back to the executor
+/// between steps so other tasks (and a cancellation) get a chance to run.
+/// Executors that resume woken tasks before handling other events (such as
+/// the browser's microtask queue) should drive [`SpectreUserKeyDerivation`]
+/// themselves and yield with a timer instead.
+pub async fn spectre_user_key_async(
+    user_name: &str,
+    user_secret: &str,
+    algorithm: SpectreAlgorithm,
+    normalization: SpectreNormalization,
+    mut progress: impl FnMut(f32),
+    cancel: &SpectreCancelToken,
+) -> Result<SpectreUserKey> {
+    let mut derivation = SpectreUserKeyDerivation::normalized(user_name, user_secret, algorithm, normalization);
+
+    loop {
+        if cancel.is_cancelled() {
+            return Err(SpectreError::Cancelled);
+        }
+        let done = derivation.step(SPECTRE_DERIVATION_STEP);
+        progress(derivation.progress());
+        if done {
+            return derivation.finish();
+        }
+        YieldNow(false).await;
+    }
+}
+
+/// Return `Pending` once, waking immediately, to let the executor run other tasks
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// scrypt's BlockMix with Salsa20/8, in place
+fn block_mix(block: &mut [u32], scratch: &mut [u32]) {
+    let half = block.len() / 32;
+    let mut x = [0u32; 16];
+    x.copy_from_slice(&block[block.len() - 16..]);
+
+    // Even sub-blocks go to the first half of the output, odd ones to the second
+    for (i, chunk) in block.chunks_exact(16).enumerate() {
+        x.iter_mut().zip(chunk).for_each(|(x, b)| *x ^= b);
+        salsa20_8(&mut x);
+        let dest = (i / 2 + (i % 2) * half) * 16;
+        scratch[dest..dest + 16].copy_from_slice(&x);
+    }
+
+    block.copy_from_slice(scratch);
+}
+
+fn salsa20_8(block: &mut [u32; 16]) {
+    fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+        x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+        x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+        x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+        x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+    }
+
+    let mut x = *block;
+    for _ in 0..4 {
+        // Columns
+        quarter_round(&mut x, 0, 4, 8, 12);
+        quarter_round(&mut x, 5, 9, 13, 1);
+        quarter_round(&mut x, 10, 14, 2, 6);
+        quarter_round(&mut x, 15, 3, 7, 11);
+        // Rows
+        quarter_round(&mut x, 0, 1, 2, 3);
+        quarter_round(&mut x, 5, 6, 7, 4);
+        quarter_round(&mut x, 10, 11, 8, 9);
+        quarter_round(&mut x, 15, 12, 13, 14);
+    }
+
+    block.iter_mut().zip(x).for_each(|(b, x)| *b = b.wrapping_add(x));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_salsa20_8() {
+        // RFC 7914 section 8
+        let input: [u8; 64] = [
+            0x7e, 0x87, 0x9a, 0x21, 0x4f, 0x3e, 0xc9, 0x86, 0x7c, 0xa9, 0x40, 0xe6, 0x41, 0x71, 0x8f, 0x26,
+            0xba, 0xee, 0x55, 0x5b, 0x8c, 0x61, 0xc1, 0xb5, 0x0d, 0xf8, 0x46, 0x11, 0x6d, 0xcd, 0x3b, 0x1d,
+            0xee, 0x24, 0xf3, 0x19, 0xdf, 0x9b, 0x3d, 0x85, 0x14, 0x12, 0x1e, 0x4b, 0x5a, 0xc5, 0xaa, 0x32,
+            0x76, 0x02, 0x1d, 0x29, 0x09, 0xc7, 0x48, 0x29, 0xed, 0xeb, 0xc6, 0x8d, 0xb8, 0xb8, 0xc2, 0x5e,
+        ];
+        let output: [u8; 64] = [
+            0xa4, 0x1f, 0x85, 0x9c, 0x66, 0x08, 0xcc, 0x99, 0x3b, 0x81, 0xca, 0xcb, 0x02, 0x0c, 0xef, 0x05,
+            0x04, 0x4b, 0x21, 0x81, 0xa2, 0xfd, 0x33, 0x7d, 0xfd, 0x7b, 0x1c, 0x63, 0x96, 0x68, 0x2f, 0x29,
+            0xb4, 0x39, 0x31, 0x68, 0xe3, 0xc9, 0xe6, 0xbc, 0xfe, 0x6b, 0xc5, 0xb7, 0xa0, 0x6d, 0x96, 0xba,
+            0xe4, 0x24, 0xcc, 0x10, 0x2c, 0x91, 0x74, 0x5c, 0x24, 0xad, 0x67, 0x3d, 0xc7, 0x61, 0x8f, 0x81,
+        ];
+
+        let mut block = [0u32; 16];
+        for (word, bytes) in block.iter_mut().zip(input.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        salsa20_8(&mut block);
+        let result: Vec<u8> = block.iter().flat_map(|word| word.to_le_bytes()).collect();
+        assert_eq!(result, output);
+    }
+
+    #[test]
+    fn test_cancelled_before_start() {
+        let cancel = SpectreCancelToken::new();
+        cancel.clone().cancel();
+        let result = spectre_user_key_with_progress("test", "test", SpectreAlgorithm::V3, SpectreNormalization::Nfc, |_| {}, &cancel);
+        assert!(matches!(result, Err(SpectreError::Cancelled)));
+    }
+}
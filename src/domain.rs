@@ -0,0 +1,72 @@
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use publicsuffix::{List, Psl};
+use crate::error::{Result, SpectreError};
+
+/// The Public Suffix List, embedded so canonicalization works offline
+const PUBLIC_SUFFIX_LIST: &str = include_str!("../data/public_suffix_list.dat");
+
+fn public_suffix_list() -> &'static List {
+    static LIST: OnceLock<List> = OnceLock::new();
+    LIST.get_or_init(|| PUBLIC_SUFFIX_LIST.parse().expect("the embedded public suffix list parses"))
+}
+
+/// Turn a URL or host name into the site name to derive passwords for
+///
+/// The scheme, user info, port, path, query and fragment are dropped, and
+/// the host is reduced to its registrable domain using the Public Suffix
+/// List: `https://www.accounts.example.co.uk/login?x=1` becomes
+/// `example.co.uk`. Punycode is decoded, so `xn--bcher-kva.de` and
+/// `bücher.de` give the same site. IP addresses are kept as they are.
+pub fn spectre_site_name(input: &str) -> Result<String> {
+    let invalid = || SpectreError::InvalidSiteName(input.to_string());
+
+    let host = url_host(input.trim());
+    let host = host.strip_suffix('.').unwrap_or(host);
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(ip.to_string());
+    }
+
+    // Lower case and punycode, as the list is written
+    let ascii = idna::domain_to_ascii_strict(host).map_err(|_| invalid())?;
+    if ascii.split('.').any(str::is_empty) {
+        return Err(invalid());
+    }
+
+    // Hosts without a registrable domain (localhost, a bare suffix) are kept, less any www.
+    let domain = match public_suffix_list().domain(ascii.as_bytes()) {
+        Some(domain) => std::str::from_utf8(domain.as_bytes()).map_err(|_| invalid())?,
+        None => ascii.strip_prefix("www.").unwrap_or(&ascii),
+    };
+
+    let (unicode, result) = idna::domain_to_unicode(domain);
+    result.map_err(|_| invalid())?;
+    Ok(unicode)
+}
+
+/// The host part of a URL, or the input itself if it's already a host
+fn url_host(input: &str) -> &str {
+    let rest = input.split_once("://").map_or(input, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+
+    // IPv6 literals are bracketed so their colons aren't taken for a port
+    if let Some(literal) = host.strip_prefix('[') {
+        return literal.split(']').next().unwrap_or_default();
+    }
+    host.split(':').next().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_host() {
+        assert_eq!(url_host("https://user:pw@www.example.com:8443/a/b?c=d#e"), "www.example.com");
+        assert_eq!(url_host("example.com/login"), "example.com");
+        assert_eq!(url_host("example.com?next=/"), "example.com");
+        assert_eq!(url_host("http://[::1]:8080/"), "::1");
+        assert_eq!(url_host("localhost:3000"), "localhost");
+    }
+}
@@ -1,3 +1,4 @@
+use alloc::string::String;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,6 +9,24 @@ pub enum SpectreError {
     #[error("Invalid result type: {0}")]
     InvalidResultType(String),
     
+    #[error("Invalid result parameter: {0}")]
+    InvalidResultParam(String),
+    
+    #[error("Invalid template: {0}")]
+    InvalidTemplate(String),
+    
+    #[error("Invalid password policy: {0}")]
+    InvalidPolicy(String),
+    
+    #[error("Invalid normalization: {0}")]
+    InvalidNormalization(String),
+    
+    #[error("Invalid site name: {0}")]
+    InvalidSiteName(String),
+    
+    #[error("Invalid one-time password seed: {0}")]
+    InvalidOtp(String),
+    
     #[error("Invalid key purpose: {0}")]
     InvalidKeyPurpose(String),
     
@@ -20,6 +39,9 @@ pub enum SpectreError {
     #[error("User key derivation failed")]
     KeyDerivationFailed,
     
+    #[error("Operation cancelled")]
+    Cancelled,
+    
     #[error("Password generation failed")]
     PasswordGenerationFailed,
     
@@ -32,15 +54,33 @@ pub enum SpectreError {
     #[error("User secret mismatch")]
     UserSecretMismatch,
     
+    #[error("No clock available for time-based one-time codes")]
+    ClockUnavailable,
+    
+    #[cfg(feature = "std")]
     #[error("File I/O error: {0}")]
     Io(#[from] std::io::Error),
     
+    #[cfg(feature = "storage")]
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
     
+    #[cfg(feature = "storage")]
+    #[error("Invalid user file: {0}")]
+    Marshal(crate::models::SpectreMarshalError),
+    
+    #[error("SSH key error: {0}")]
+    SshKey(String),
+    
+    #[error("Self-test failed: {0} vector(s) did not match")]
+    SelfTestFailed(usize),
+    
     #[error("Missing required field: {0}")]
     MissingField(String),
+    
+    #[error("Security question conflict: {0}")]
+    QuestionConflict(String),
 }
 
-pub type Result<T> = std::result::Result<T, SpectreError>;
+pub type Result<T> = core::result::Result<T, SpectreError>;
 
@@ -0,0 +1,225 @@
+use alloc::{format, string::{String, ToString}};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::Sha256;
+use core::fmt;
+use zeroize::Zeroizing;
+use crate::types::SpectreNormalization;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const LEFT_ARMS: &[&str] = &["╔", "╚", "╰", "═"];
+const BODIES: &[&str] = &["█", "░", "▒", "▓", "☺", "☻"];
+const RIGHT_ARMS: &[&str] = &["╗", "╝", "╯", "═"];
+const ACCESSORIES: &[&str] = &[
+    "◈", "◎", "◐", "◑", "◒", "◓", "☀", "☁", "☂", "☃",
+    "☄", "★", "☆", "☎", "☏", "⎈", "⌂", "☘", "☢", "☣",
+    "☕", "⌚", "⌛", "⏰", "⚡", "⛄", "⛅", "☔", "♔", "♕",
+    "♖", "♗", "♘", "♙", "♚", "♛", "♜", "♝", "♞", "♟",
+    "♨", "♩", "♪", "♫", "⚐", "⚑", "⚔", "⚖", "⚙", "⚠",
+    "⌘", "⏎", "✄", "✆", "✈", "✉", "✌",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(u8)]
+pub enum SpectreIdenticonColor {
+    #[default]
+    Unset = 0,
+    Red = 1,
+    Green = 2,
+    Yellow = 3,
+    Blue = 4,
+    Magenta = 5,
+    Cyan = 6,
+    Mono = 7,
+}
+
+impl SpectreIdenticonColor {
+    const ALL: [Self; 7] = [
+        Self::Red, Self::Green, Self::Yellow, Self::Blue, Self::Magenta, Self::Cyan, Self::Mono,
+    ];
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Unset),
+            1..=7 => Some(Self::ALL[value as usize - 1]),
+            _ => None,
+        }
+    }
+
+    /// ANSI SGR foreground code (the upstream CLI uses the color number as the terminal color)
+    pub fn ansi_code(&self) -> Option<u8> {
+        match self {
+            Self::Unset => None,
+            color => Some(30 + *color as u8),
+        }
+    }
+
+    /// CSS color value, tuned for display on a dark background
+    pub fn css(&self) -> &'static str {
+        match self {
+            Self::Unset | Self::Mono => "inherit",
+            Self::Red => "#f87171",
+            Self::Green => "#4ade80",
+            Self::Yellow => "#facc15",
+            Self::Blue => "#60a5fa",
+            Self::Magenta => "#e879f9",
+            Self::Cyan => "#22d3ee",
+        }
+    }
+}
+
+/// A visual fingerprint of a user's name and secret, identical to upstream Spectre clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct SpectreIdenticon {
+    pub left_arm: &'static str,
+    pub body: &'static str,
+    pub right_arm: &'static str,
+    pub accessory: &'static str,
+    pub color: SpectreIdenticonColor,
+}
+
+impl SpectreIdenticon {
+    pub fn is_unset(&self) -> bool {
+        self.color == SpectreIdenticonColor::Unset
+    }
+
+    /// The four glyphs without any coloring
+    pub fn render_text(&self) -> String {
+        format!("{}{}{}{}", self.left_arm, self.body, self.right_arm, self.accessory)
+    }
+
+    /// The glyphs wrapped in ANSI color escapes for terminals
+    pub fn render_ansi(&self) -> String {
+        match self.color.ansi_code() {
+            Some(code) => format!("\x1b[{}m{}\x1b[0m", code, self.render_text()),
+            None => self.render_text(),
+        }
+    }
+
+    /// An HTML fragment with the color applied inline
+    pub fn render_html(&self) -> String {
+        format!(
+            "<span class=\"spectre-identicon\" style=\"color: {}\">{}</span>",
+            self.color.css(),
+            self.render_text(),
+        )
+    }
+
+    /// Upstream's marshalled form, e.g. `2:╚☻╯⛄`
+    pub fn encode(&self) -> String {
+        if self.is_unset() {
+            return String::new();
+        }
+        format!("{}:{}", self.color as u8, self.render_text())
+    }
+
+    /// Parse the form produced by [`SpectreIdenticon::encode`]
+    pub fn decode(encoded: &str) -> Option<Self> {
+        if encoded.is_empty() {
+            return Some(Self::default());
+        }
+
+        let (color, glyphs) = encoded.split_once(':')?;
+        let color = SpectreIdenticonColor::from_u8(color.parse().ok()?)?;
+        let mut chars = glyphs.chars().map(|c| c.to_string());
+        let mut glyph = |set: &[&'static str]| {
+            let c = chars.next()?;
+            set.iter().find(|g| **g == c).copied()
+        };
+        let identicon = Self {
+            left_arm: glyph(LEFT_ARMS)?,
+            body: glyph(BODIES)?,
+            right_arm: glyph(RIGHT_ARMS)?,
+            accessory: glyph(ACCESSORIES)?,
+            color,
+        };
+
+        chars.next().is_none().then_some(identicon)
+    }
+}
+
+impl fmt::Display for SpectreIdenticon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render_text())
+    }
+}
+
+impl Serialize for SpectreIdenticon {
+    fn serialize<S: Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.encode())
+    }
+}
+
+impl<'de> Deserialize<'de> for SpectreIdenticon {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> core::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Encoded(String),
+            // Files written before identicons were modelled stored 4 raw bytes;
+            // they can't be mapped back, so they're left unset until the next login.
+            Legacy(#[allow(dead_code)] [u8; 4]),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Encoded(encoded) => Self::decode(&encoded)
+                .ok_or_else(|| serde::de::Error::custom(format!("invalid identicon: {}", encoded))),
+            Repr::Legacy(_) => Ok(Self::default()),
+        }
+    }
+}
+
+/// Generate the identicon for a user
+///
+/// Like upstream, the seed is HMAC-SHA256 of the user name keyed with the
+/// user secret, so it's cheap enough to update while the user is typing.
+/// Both are normalized to NFC first, so the identicon doesn't change with
+/// the way an accent was typed.
+pub fn spectre_identicon(user_name: &str, user_secret: &str) -> SpectreIdenticon {
+    spectre_identicon_normalized(user_name, user_secret, SpectreNormalization::default())
+}
+
+/// Generate the identicon for a user, normalizing the name and secret as given
+///
+/// Use the user's own normalization so the identicon matches the one their
+/// key was derived with.
+pub fn spectre_identicon_normalized(
+    user_name: &str,
+    user_secret: &str,
+    normalization: SpectreNormalization,
+) -> SpectreIdenticon {
+    let user_secret = Zeroizing::new(normalization.apply(user_secret).into_owned());
+    let mut mac = HmacSha256::new_from_slice(user_secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(normalization.apply(user_name).as_bytes());
+    let seed = mac.finalize().into_bytes();
+
+    SpectreIdenticon {
+        left_arm: LEFT_ARMS[seed[0] as usize % LEFT_ARMS.len()],
+        body: BODIES[seed[1] as usize % BODIES.len()],
+        right_arm: RIGHT_ARMS[seed[2] as usize % RIGHT_ARMS.len()],
+        accessory: ACCESSORIES[seed[3] as usize % ACCESSORIES.len()],
+        color: SpectreIdenticonColor::ALL[seed[4] as usize % SpectreIdenticonColor::ALL.len()],
+    }
+}
+
+/// Render an identicon as plain text
+pub fn spectre_identicon_render(identicon: SpectreIdenticon) -> String {
+    identicon.render_text()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identicon_encoding() {
+        let identicon = spectre_identicon("Robert Lee Mitchell", "banana colored duckling");
+        assert_eq!(identicon.encode(), "2:╚☻╯⛄");
+        assert_eq!(SpectreIdenticon::decode("2:╚☻╯⛄"), Some(identicon));
+        assert_eq!(SpectreIdenticon::decode(""), Some(SpectreIdenticon::default()));
+        assert_eq!(SpectreIdenticon::decode("9:╚☻╯⛄"), None);
+        assert_eq!(SpectreIdenticon::decode("2:╚☻╯"), None);
+    }
+}
@@ -22,27 +22,93 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
 pub mod algorithm;
+pub mod batch;
+pub mod derivation;
+#[cfg(feature = "std")]
+pub mod domain;
 pub mod error;
+pub mod identicon;
+#[cfg(feature = "storage")]
+pub mod login;
+#[cfg(feature = "storage")]
 pub mod models;
+pub mod otp;
+#[cfg(feature = "storage")]
 pub mod marshal;
+pub mod policy;
+pub mod question;
+pub mod secret;
+pub mod selftest;
+#[cfg(feature = "ssh")]
+pub mod ssh;
 pub mod types;
 pub mod util;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod words;
 
 // Re-export commonly used items
 pub use algorithm::{
     spectre_user_key, 
+    spectre_user_key_normalized,
     spectre_site_result, 
-    spectre_identicon,
-    spectre_identicon_preview,
-    spectre_identicon_render,
+    spectre_site_request_result,
     spectre_site_state,
+    spectre_site_otp,
     SpectreUserKey,
 };
+pub use batch::{spectre_site_results, SpectreSiteRequest};
+#[cfg(feature = "parallel")]
+pub use batch::spectre_site_results_parallel;
+pub use derivation::{
+    spectre_user_key_async,
+    spectre_user_key_with_progress,
+    SpectreCancelToken,
+    SpectreUserKeyDerivation,
+    SPECTRE_DERIVATION_STEP,
+};
+#[cfg(feature = "std")]
+pub use domain::spectre_site_name;
 pub use error::{SpectreError, Result};
+pub use identicon::{
+    spectre_identicon,
+    spectre_identicon_normalized,
+    spectre_identicon_render,
+    SpectreIdenticon,
+    SpectreIdenticonColor,
+};
+#[cfg(feature = "storage")]
+pub use login::{spectre_site_login, spectre_user_set_login};
+#[cfg(feature = "storage")]
 pub use models::*;
-pub use marshal::{spectre_marshal_read, spectre_marshal_write, spectre_marshal_auth, spectre_user_path};
+#[cfg(feature = "storage")]
+pub use marshal::{spectre_marshal_read, spectre_marshal_parse, spectre_marshal_write, spectre_marshal_export, spectre_marshal_auth, spectre_marshal_unlock, spectre_user_path};
+pub use otp::{
+    SpectreOtp,
+    SpectreOtpAlgorithm,
+    SpectreOtpKind,
+    SPECTRE_OTP_DIGITS_DEFAULT,
+    SPECTRE_OTP_PERIOD_DEFAULT,
+};
+pub use policy::{SpectrePasswordPolicy, SPECTRE_POLICY_LENGTH_MAX};
+pub use question::spectre_question_keyword;
+pub use secret::{SpectreSecret, SpectreSecretBytes};
+pub use selftest::{spectre_selftest, SpectreTestOutcome, SpectreTestVector, SPECTRE_TEST_VECTORS};
+#[cfg(feature = "ssh")]
+pub use ssh::{spectre_site_ssh_key, SpectreSshKey};
 pub use types::*;
+pub use words::{
+    SpectreWordList,
+    SpectreWordsParam,
+    SPECTRE_WORDS_COUNT_DEFAULT,
+    SPECTRE_WORDS_COUNT_MAX,
+    SPECTRE_WORDS_COUNT_MIN,
+};
 
 // Re-export utility functions (parse_bool and zero_string are always available)
 pub use util::{parse_bool, zero_string};
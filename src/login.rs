@@ -0,0 +1,69 @@
+use crate::algorithm::{spectre_site_request_result, spectre_site_state, SpectreUserKey};
+use crate::batch::SpectreSiteRequest;
+use crate::error::{Result, SpectreError};
+use crate::models::{SpectreMarshalledSite, SpectreMarshalledUser};
+use crate::types::*;
+
+/// The login name to use for a site
+///
+/// A login set on the site wins; without one the user's standard login is
+/// used, and without that a login is generated from the Name template.
+/// Logins use the identification purpose and the initial counter (0), as
+/// upstream's clients do, so rotating a password doesn't change its login.
+/// Stored logins are decrypted from their state.
+pub fn spectre_site_login(
+    user_key: &SpectreUserKey,
+    user: &SpectreMarshalledUser,
+    site: &SpectreMarshalledSite,
+) -> Result<String> {
+    let (login_type, login_state, login_template) = if site.login_type != SpectreResultType::None {
+        (site.login_type, site.login_state.as_deref(), site.login_template.as_ref())
+    } else if user.login_type != SpectreResultType::None {
+        (user.login_type, user.login_state.as_deref(), None)
+    } else {
+        (SPECTRE_RESULT_DEFAULT_LOGIN, None, None)
+    };
+
+    if login_type.is_stateful() && login_state.is_none() {
+        return Err(SpectreError::MissingField(format!("login name for {}", site.site_name)));
+    }
+
+    let mut request = SpectreSiteRequest::new(&site.site_name, login_type)
+        .with_counter(SPECTRE_COUNTER_INITIAL)
+        .with_purpose(SpectreKeyPurpose::Identification, None);
+    request.result_param = match login_template {
+        Some(template) if login_type.is_custom() => Some(template.as_str()),
+        _ => login_state,
+    };
+
+    spectre_site_request_result(user_key, &request)
+}
+
+/// Set the user's standard login, used by sites without a login of their own
+///
+/// The login is stored encrypted, like a personal password. `None` clears
+/// it, so sites fall back to generated logins again.
+pub fn spectre_user_set_login(
+    user_key: &SpectreUserKey,
+    user: &mut SpectreMarshalledUser,
+    login: Option<&str>,
+) -> Result<()> {
+    let Some(login) = login else {
+        user.login_type = SpectreResultType::None;
+        user.login_state = None;
+        return Ok(());
+    };
+
+    user.login_state = Some(spectre_site_state(
+        user_key,
+        &user.user_name,
+        SpectreResultType::PersonalPassword,
+        login,
+        SPECTRE_COUNTER_INITIAL,
+        SpectreKeyPurpose::Identification,
+        None,
+    )?);
+    user.login_type = SpectreResultType::PersonalPassword;
+
+    Ok(())
+}
@@ -1,15 +1,24 @@
 use std::path::PathBuf;
+use chrono::{DateTime, Utc};
 use crate::error::{Result, SpectreError};
 use crate::models::*;
-use crate::algorithm::{spectre_user_key, spectre_identicon};
+use crate::algorithm::{spectre_site_otp, spectre_site_request_result, spectre_site_state, spectre_user_key_normalized, SpectreUserKey};
+use crate::batch::SpectreSiteRequest;
+use crate::identicon::spectre_identicon_normalized;
+use crate::login::spectre_site_login;
+use crate::secret::SpectreSecret;
+use crate::types::*;
 
-#[cfg(feature = "cli")]
 use std::fs::{self, File};
-#[cfg(feature = "cli")]
 use std::io::{Read, Write};
 
-/// Read a marshalled user file (CLI only)
-#[cfg(feature = "cli")]
+mod flat;
+mod json;
+
+use flat::{parse_flat_format, write_flat_format};
+use json::{parse_json_format, write_json_format};
+
+/// Read a marshalled user file
 pub fn spectre_marshal_read(file_path: &PathBuf) -> Result<(SpectreMarshalledFile, Option<SpectreMarshalledUser>)> {
     if !file_path.exists() {
         return Ok((
@@ -22,99 +31,296 @@ pub fn spectre_marshal_read(file_path: &PathBuf) -> Result<(SpectreMarshalledFil
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
 
-    // Try to parse as JSON first
-    match serde_json::from_str::<SpectreMarshalledUser>(&contents) {
-        Ok(user) => {
-            let file_meta = SpectreMarshalledFile::new(SpectreFormat::JSON, user.redacted);
-            Ok((file_meta, Some(user)))
-        }
-        Err(_) => {
-            // Try to parse as flat format
-            parse_flat_format(&contents)
-        }
-    }
+    spectre_marshal_parse(&contents)
 }
 
-/// Read a marshalled user file (stub for non-CLI builds)
-#[cfg(not(feature = "cli"))]
-pub fn spectre_marshal_read(_file_path: &PathBuf) -> Result<(SpectreMarshalledFile, Option<SpectreMarshalledUser>)> {
-    Err(SpectreError::InvalidFileFormat("File I/O not available in this build".to_string()))
+/// Parse the contents of a marshalled user file, JSON or flat
+pub fn spectre_marshal_parse(contents: &str) -> Result<(SpectreMarshalledFile, Option<SpectreMarshalledUser>)> {
+    if !contents.trim_start().starts_with('{') {
+        return parse_flat_format(contents);
+    }
+
+    parse_json_format(contents)
 }
 
-/// Write a marshalled user file (CLI only)
-#[cfg(feature = "cli")]
+/// Write a marshalled user file
+///
+/// See [`spectre_marshal_export`] for what is written. Unredacted files hold
+/// plaintext secrets, so on Unix only their owner can read them.
 pub fn spectre_marshal_write(
     file_path: &PathBuf,
     format: SpectreFormat,
     user: &SpectreMarshalledUser,
+    user_keys: &[SpectreUserKey],
 ) -> Result<()> {
+    if format == SpectreFormat::None {
+        return Ok(());
+    }
+    let contents = spectre_marshal_export(format, user, user_keys)?;
+
     // Ensure directory exists
     if let Some(parent) = file_path.parent() {
         fs::create_dir_all(parent)?;
     }
 
-    let contents = match format {
-        SpectreFormat::JSON => {
-            serde_json::to_string_pretty(user)?
-        }
-        SpectreFormat::Flat => {
-            write_flat_format(user)?
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if !user.redacted {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // An existing file keeps its mode on open; tighten it before writing
+        if file_path.exists() {
+            fs::set_permissions(file_path, fs::Permissions::from_mode(0o600))?;
         }
-        SpectreFormat::None => {
-            return Ok(());
-        }
-    };
-
-    let mut file = File::create(file_path)?;
-    file.write_all(contents.as_bytes())?;
+    }
+    options.open(file_path)?.write_all(contents.as_bytes())?;
 
     Ok(())
 }
 
-/// Write a marshalled user file (stub for non-CLI builds)
-#[cfg(not(feature = "cli"))]
-pub fn spectre_marshal_write(
-    _file_path: &PathBuf,
-    _format: SpectreFormat,
-    _user: &SpectreMarshalledUser,
-) -> Result<()> {
-    Err(SpectreError::InvalidFileFormat("File I/O not available in this build".to_string()))
-}
-
-/// Parse flat format (simplified version)
-#[allow(dead_code)]
-fn parse_flat_format(_contents: &str) -> Result<(SpectreMarshalledFile, Option<SpectreMarshalledUser>)> {
-    // This is a simplified parser - the full implementation would be more complex
-    // For now, return an error to encourage JSON format
-    Err(SpectreError::InvalidFileFormat("Flat format parsing not fully implemented. Please use JSON format.".to_string()))
-}
+/// Marshal a user into the contents of a user file
+///
+/// Redacted users are written as they are held: site passwords, login names
+/// and answers only appear as the encrypted states of personal results.
+/// Unredacted users must be unlocked with their user key, and are written
+/// with every site's password, login name and answers in plaintext, for
+/// backups or moving to another app. Reading the file back encrypts them
+/// again in [`spectre_marshal_auth`]. When the user name isn't ASCII, sites
+/// of an algorithm that counts its length differently need their own key
+/// (see [`SpectreUserKey::covers_algorithm`]), passed along with the user's.
+pub fn spectre_marshal_export(
+    format: SpectreFormat,
+    user: &SpectreMarshalledUser,
+    user_keys: &[SpectreUserKey],
+) -> Result<String> {
+    let user_keys = match (user.redacted, user_keys) {
+        (true, _) => None,
+        (false, []) => return Err(SpectreError::MissingField("user key to write an unredacted file".to_string())),
+        (false, user_keys) if user_keys.iter().any(|user_key| user_key.matches_key_id(&user.key_id)) => Some(user_keys),
+        (false, _) => return Err(SpectreError::UserSecretMismatch),
+    };
 
-/// Write flat format (simplified version)
-#[allow(dead_code)]
-fn write_flat_format(_user: &SpectreMarshalledUser) -> Result<String> {
-    // This is a simplified writer - the full implementation would be more complex
-    Err(SpectreError::InvalidFileFormat("Flat format writing not fully implemented. Please use JSON format.".to_string()))
+    match format {
+        SpectreFormat::JSON => write_json_format(user, user_keys),
+        SpectreFormat::Flat => write_flat_format(user, user_keys),
+        SpectreFormat::None => Ok(String::new()),
+    }
 }
 
 /// Authenticate user with their secret
+///
+/// Returns the user key, derived with the user's algorithm and normalization.
+/// Plaintext passwords, logins and answers read from an unredacted export
+/// are encrypted with it.
 pub fn spectre_marshal_auth(
     user: &mut SpectreMarshalledUser,
     user_secret: &str,
-) -> Result<()> {
-    let user_key = spectre_user_key(&user.user_name, user_secret, user.algorithm)?;
+) -> Result<SpectreUserKey> {
+    let user_key = spectre_user_key_normalized(&user.user_name, user_secret, user.algorithm, user.normalization)?;
+    spectre_marshal_unlock(user, user_secret, &user_key)?;
     
+    Ok(user_key)
+}
+
+/// Authenticate a user with a user key already derived from their secret
+///
+/// Like [`spectre_marshal_auth`], for callers that derive the key themselves,
+/// e.g. with [`SpectreUserKeyDerivation`](crate::SpectreUserKeyDerivation) to
+/// show progress. The key must be derived with the user's algorithm and
+/// normalization.
+pub fn spectre_marshal_unlock(
+    user: &mut SpectreMarshalledUser,
+    user_secret: &str,
+    user_key: &SpectreUserKey,
+) -> Result<()> {
     // Verify key ID matches
-    if user.key_id != user_key.key_id {
+    if !user_key.matches_key_id(&user.key_id) {
         return Err(SpectreError::UserSecretMismatch);
     }
     
     // Update identicon
-    let identicon = spectre_identicon(&user.user_name, user_secret)?;
-    user.identicon = identicon;
+    user.identicon = spectre_identicon_normalized(&user.user_name, user_secret, user.normalization);
+    
+    if user.plaintext_states {
+        seal_states(user, user_key, user_secret)?;
+    }
     
     Ok(())
 }
 
+/// Encrypt the plaintext states of an unredacted export, as personal results
+///
+/// Each state is sealed with the counter, purpose and context it's read
+/// back with: the site's counter for its password, the initial counter for
+/// logins and answers, and the keyword for answers. Sites whose algorithm
+/// the user key doesn't cover get their own key, derived from the secret.
+fn seal_states(user: &mut SpectreMarshalledUser, user_key: &SpectreUserKey, user_secret: &str) -> Result<()> {
+    let seal = |key: &SpectreUserKey, request: SpectreSiteRequest, state: &mut Option<String>| -> Result<()> {
+        if let (true, Some(plaintext)) = (request.result_type.is_stateful(), state.take().map(SpectreSecret::new)) {
+            *state = Some(spectre_site_state(
+                key,
+                request.site_name,
+                request.result_type,
+                &plaintext,
+                request.key_counter,
+                request.key_purpose,
+                request.key_context,
+            )?);
+        }
+        Ok(())
+    };
+    let login = |name, result_type| SpectreSiteRequest::new(name, result_type)
+        .with_counter(SPECTRE_COUNTER_INITIAL)
+        .with_purpose(SpectreKeyPurpose::Identification, None);
+
+    seal(user_key, login(&user.user_name, user.login_type), &mut user.login_state)?;
+    let mut user_keys = vec![user_key.clone()];
+    for site in &mut user.sites {
+        if !user_keys.iter().any(|user_key| user_key.covers_algorithm(&user.user_name, site.algorithm)) {
+            user_keys.push(spectre_user_key_normalized(&user.user_name, user_secret, site.algorithm, user.normalization)?);
+        }
+        let site_key = &site_user_key(&user.user_name, &user_keys, site.algorithm)?;
+
+        let password = SpectreSiteRequest::new(&site.site_name, site.result_type).with_counter(site.counter);
+        seal(site_key, password, &mut site.result_state)?;
+        seal(site_key, login(&site.site_name, site.login_type), &mut site.login_state)?;
+        for question in &mut site.questions {
+            let answer = SpectreSiteRequest::new(&site.site_name, question.question_type)
+                .with_counter(SPECTRE_COUNTER_INITIAL)
+                .with_purpose(SpectreKeyPurpose::Recovery, Some(&question.keyword));
+            seal(site_key, answer, &mut question.state)?;
+        }
+    }
+    user.plaintext_states = false;
+
+    Ok(())
+}
+
+/// Parse a key ID written as 64 hex digits
+pub(crate) fn parse_key_id(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut key_id = [0u8; 32];
+    for (byte, digits) in key_id.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(key_id)
+}
+
+/// Write a key ID as upstream does, in upper case hex
+pub(crate) fn key_id_hex(key_id: &[u8; 32]) -> String {
+    key_id.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+/// Parse an upstream timestamp, e.g. `2017-08-07T20:33:17Z`
+pub(crate) fn parse_date(date: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(date).ok().map(|date| date.with_timezone(&Utc))
+}
+
+pub(crate) fn format_date(date: &DateTime<Utc>) -> String {
+    date.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// The user key for a site created with `algorithm`, from the keys given
+///
+/// A key of another algorithm is reused when it covers the site's; for
+/// non-ASCII user names that isn't always so, and the key derived for the
+/// site's algorithm must be among them.
+pub(crate) fn site_user_key(user_name: &str, user_keys: &[SpectreUserKey], algorithm: SpectreAlgorithm) -> Result<SpectreUserKey> {
+    let user_key = user_keys.iter().find(|user_key| user_key.algorithm == algorithm)
+        .or_else(|| user_keys.iter().find(|user_key| user_key.covers_algorithm(user_name, algorithm)))
+        .ok_or_else(|| SpectreError::MissingField(format!("user key for algorithm {}", algorithm)))?;
+
+    let mut site_key = user_key.clone();
+    site_key.algorithm = algorithm;
+    Ok(site_key)
+}
+
+/// A site's password in plaintext, or nothing for a personal password that was never set
+pub(crate) fn site_password(user_key: &SpectreUserKey, site: &SpectreMarshalledSite) -> Result<String> {
+    if site.result_type == SpectreResultType::None || (site.result_type.is_stateful() && site.result_state.is_none()) {
+        return Ok(String::new());
+    }
+
+    let words = site.result_words.as_ref().map(ToString::to_string);
+    let mut request = SpectreSiteRequest::new(&site.site_name, site.result_type).with_counter(site.counter);
+    request.result_param = match (&site.result_template, &words) {
+        (Some(template), _) if site.result_type.is_custom() => Some(template.as_str()),
+        (_, Some(words)) if site.result_type.word_list().is_some() => Some(words.as_str()),
+        _ => site.result_state.as_deref(),
+    };
+    if let Some(policy) = &site.policy {
+        request = request.with_policy(policy);
+    }
+
+    spectre_site_request_result(user_key, &request)
+}
+
+/// What an unredacted export keeps as a site's password: the password, or for
+/// a one-time code the seed it's generated from, since a code expires
+pub(crate) fn site_export_password(user_key: &SpectreUserKey, site: &SpectreMarshalledSite) -> Result<String> {
+    match site.result_state.as_deref() {
+        Some(state) if site.result_type.is_one_time_code() => {
+            let request = SpectreSiteRequest::new(&site.site_name, site.result_type)
+                .with_counter(site.counter)
+                .with_param(state);
+            Ok(spectre_site_otp(user_key, &request)?.uri())
+        }
+        _ => site_password(user_key, site),
+    }
+}
+
+/// The answer to a site's security question in plaintext, or nothing for a personal answer that was never set
+pub(crate) fn question_answer(user_key: &SpectreUserKey, site: &SpectreMarshalledSite, question: &SpectreMarshalledQuestion) -> Result<String> {
+    if question.question_type == SpectreResultType::None || (question.question_type.is_stateful() && question.state.is_none()) {
+        return Ok(String::new());
+    }
+
+    let mut request = SpectreSiteRequest::new(&site.site_name, question.question_type)
+        .with_counter(SPECTRE_COUNTER_INITIAL)
+        .with_purpose(SpectreKeyPurpose::Recovery, Some(&question.keyword));
+    request.result_param = match &question.template {
+        Some(template) if question.question_type.is_custom() => Some(template.as_str()),
+        _ => question.state.as_deref(),
+    };
+
+    spectre_site_request_result(user_key, &request)
+}
+
+/// A site's login name in plaintext, or nothing for a site without a login
+///
+/// Sites without a login of their own show the user's standard login; if the
+/// user has none either, no login is made up for them. The standard login is
+/// opened with the user's key, which for a non-ASCII name may not be the site's.
+pub(crate) fn site_login(
+    user_key: &SpectreUserKey,
+    site_key: &SpectreUserKey,
+    user: &SpectreMarshalledUser,
+    site: &SpectreMarshalledSite,
+) -> Result<Option<String>> {
+    if site.login_type == SpectreResultType::None {
+        return user_login(user_key, user);
+    }
+
+    spectre_site_login(site_key, user, site).map(Some)
+}
+
+/// The user's standard login in plaintext, if they set one
+pub(crate) fn user_login(user_key: &SpectreUserKey, user: &SpectreMarshalledUser) -> Result<Option<String>> {
+    let Some(login_state) = user.login_state.as_deref().filter(|_| user.login_type.is_stateful()) else {
+        return Ok(None);
+    };
+
+    let mut request = SpectreSiteRequest::new(&user.user_name, user.login_type)
+        .with_counter(SPECTRE_COUNTER_INITIAL)
+        .with_purpose(SpectreKeyPurpose::Identification, None);
+    request.result_param = Some(login_state);
+    spectre_site_request_result(user_key, &request).map(Some)
+}
+
 /// Get the default user file path (CLI only)
 #[cfg(feature = "cli")]
 pub fn spectre_user_path(user_name: &str, format: SpectreFormat) -> Option<PathBuf> {
@@ -139,9 +345,10 @@ pub fn spectre_user_path(_user_name: &str, _format: SpectreFormat) -> Option<Pat
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::*;
+    use crate::identicon::SpectreIdenticon;
 
     #[test]
+    #[cfg(feature = "cli")]
     fn test_user_path() {
         let path = spectre_user_path("testuser", SpectreFormat::JSON);
         assert!(path.is_some());
@@ -152,7 +359,7 @@ mod tests {
     fn test_json_serialization() {
         let user = SpectreMarshalledUser::new(
             "testuser".to_string(),
-            [0, 1, 2, 3],
+            SpectreIdenticon::default(),
             [0u8; 32],
             SPECTRE_ALGORITHM_CURRENT,
         );
@@ -0,0 +1,345 @@
+use std::fmt::Write;
+use chrono::{DateTime, Utc};
+use crate::algorithm::SpectreUserKey;
+use crate::error::{Result, SpectreError};
+use crate::identicon::SpectreIdenticon;
+use crate::models::*;
+use crate::types::*;
+use super::{format_date, key_id_hex, parse_date, parse_key_id, site_export_password, site_login, site_user_key};
+
+/// Header metadata of a flat file, between its two `##` lines
+struct FlatHeader {
+    format: u32,
+    date: Option<DateTime<Utc>>,
+    user_name: Option<String>,
+    identicon: SpectreIdenticon,
+    key_id: Option<[u8; 32]>,
+    algorithm: SpectreAlgorithm,
+    default_type: SpectreResultType,
+    redacted: bool,
+}
+
+impl Default for FlatHeader {
+    fn default() -> Self {
+        Self {
+            format: 1,
+            date: None,
+            user_name: None,
+            identicon: SpectreIdenticon::default(),
+            key_id: None,
+            algorithm: SPECTRE_ALGORITHM_CURRENT,
+            default_type: SPECTRE_RESULT_DEFAULT_RESULT,
+            redacted: true,
+        }
+    }
+}
+
+impl FlatHeader {
+    /// Read one `# Name: value` header line; lines without a colon are comments
+    fn read(&mut self, header: &str, line: usize) -> Result<()> {
+        let Some((name, value)) = header.split_once(':') else {
+            return Ok(());
+        };
+        let value = value.trim_start_matches(' ');
+        let illegal = |what: &str| marshal_error(SpectreMarshalErrorType::ErrorIllegal, line, format!("Invalid {}: {}", what, value));
+
+        match name.trim_start_matches(' ') {
+            "Format" => {
+                self.format = value.parse().map_err(|_| illegal("format"))?;
+                if self.format > 1 {
+                    return Err(marshal_error(SpectreMarshalErrorType::ErrorFormat, line, format!("Unexpected format: {}", value)));
+                }
+            }
+            "Date" => self.date = Some(parse_date(value).ok_or_else(|| illegal("export date"))?),
+            "Passwords" => self.redacted = value != "VISIBLE",
+            "Full Name" | "User Name" => self.user_name = Some(value.to_string()),
+            "Identicon" => self.identicon = SpectreIdenticon::decode(value).ok_or_else(|| illegal("user identicon"))?,
+            "Key ID" => self.key_id = Some(parse_key_id(value).ok_or_else(|| illegal("key ID"))?),
+            "Algorithm" => {
+                self.algorithm = value.parse().ok()
+                    .and_then(|version: u32| SpectreAlgorithm::try_from(version).ok())
+                    .ok_or_else(|| illegal("user algorithm version"))?;
+            }
+            "Default Type" => {
+                self.default_type = value.parse().ok()
+                    .and_then(|code| SpectreResultType::from_upstream_code(code).ok())
+                    .ok_or_else(|| illegal("user default type"))?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// The user the header describes, once it ends on `line`
+    fn user(&self, line: usize) -> Result<SpectreMarshalledUser> {
+        let missing = |header: &str| marshal_error(SpectreMarshalErrorType::ErrorMissing, line, format!("Missing header: {}", header));
+        let user_name = self.user_name.clone().ok_or_else(|| missing("Full Name"))?;
+        let key_id = self.key_id.ok_or_else(|| missing("Key ID"))?;
+
+        let mut user = SpectreMarshalledUser::new(user_name, self.identicon, key_id, self.algorithm);
+        user.redacted = self.redacted;
+        user.plaintext_states = !self.redacted;
+        user.default_type = self.default_type;
+        user.last_used = self.date.unwrap_or_else(Utc::now);
+        Ok(user)
+    }
+}
+
+/// Splits a site line as upstream reads it: leading spaces are skipped, and
+/// a field runs up to the first of its delimiters
+struct FlatFields<'a>(&'a str);
+
+impl<'a> FlatFields<'a> {
+    fn next(&mut self, delimiters: &[char]) -> &'a str {
+        let rest = self.0.trim_start_matches(' ');
+        let (field, rest) = match rest.find(delimiters) {
+            Some(end) => (&rest[..end], &rest[end + 1..]),
+            None => (rest, ""),
+        };
+        self.0 = rest;
+        field
+    }
+
+    fn rest(&mut self) -> &'a str {
+        self.next(&[])
+    }
+}
+
+/// Parse an upstream `.mpsites` flat export
+///
+/// Comments and headers start with `#`; the headers sit between two `##`
+/// lines. Each site is one line after the header: when it was last used,
+/// how often, `type:algorithm:counter` (format 1) or `type[:algorithm]`
+/// (format 0, which has no counter or login), then the login name, site
+/// name and password separated by tabs. Protected (redacted) exports hold
+/// encrypted states; visible ones hold plaintext, which
+/// [`spectre_marshal_auth`](crate::spectre_marshal_auth) encrypts.
+pub(super) fn parse_flat_format(contents: &str) -> Result<(SpectreMarshalledFile, Option<SpectreMarshalledUser>)> {
+    let mut header = FlatHeader::default();
+    let mut header_started = false;
+    let mut user: Option<SpectreMarshalledUser> = None;
+
+    for (index, text) in contents.lines().enumerate() {
+        let line = index + 1;
+
+        if let Some(comment) = text.strip_prefix('#') {
+            if user.is_some() {
+                // Comments after the header
+                continue;
+            }
+            if comment.starts_with('#') {
+                // ## starts and ends the header
+                if header_started {
+                    user = Some(header.user(line)?);
+                }
+                header_started = true;
+            } else if header_started {
+                header.read(comment, line)?;
+            }
+            continue;
+        }
+
+        let Some(user) = user.as_mut() else {
+            continue;
+        };
+        if text.trim().is_empty() {
+            continue;
+        }
+        user.add_site(parse_flat_site(text, &header, line)?);
+    }
+
+    let Some(user) = user else {
+        let (error_type, message) = if header_started {
+            (SpectreMarshalErrorType::ErrorMissing, "Header doesn't end")
+        } else {
+            (SpectreMarshalErrorType::ErrorFormat, "No flat file header")
+        };
+        return Err(SpectreError::Marshal(SpectreMarshalError::new(error_type, None, message)));
+    };
+
+    let mut file = SpectreMarshalledFile::new(SpectreFormat::Flat, header.redacted);
+    if let Some(date) = header.date {
+        file.date = date;
+    }
+    Ok((file, Some(user)))
+}
+
+fn parse_flat_site(text: &str, header: &FlatHeader, line: usize) -> Result<SpectreMarshalledSite> {
+    let mut fields = FlatFields(text);
+    let last_used = fields.next(&[' ', '\t']);
+    let uses = fields.next(&[' ', '\t']);
+    let mut type_fields = fields.next(&[' ', '\t']).splitn(3, ':');
+    let result_type = type_fields.next().unwrap_or_default();
+    let (algorithm, counter, login) = match header.format {
+        0 => (type_fields.next().unwrap_or("0"), "1", ""),
+        _ => (type_fields.next().unwrap_or_default(), type_fields.next().unwrap_or_default(), fields.next(&['\t'])),
+    };
+    let site_name = fields.next(&['\t']);
+    let state = fields.rest();
+
+    if [last_used, uses, result_type, algorithm, counter, site_name].contains(&"") {
+        return Err(marshal_error(SpectreMarshalErrorType::ErrorMissing, line, format!(
+            "Missing one of: lastUsed={}, uses={}, type={}, version={}, counter={}, siteName={}",
+            last_used, uses, result_type, algorithm, counter, site_name
+        )));
+    }
+    let illegal = |what: &str, value: &str| marshal_error(
+        SpectreMarshalErrorType::ErrorIllegal, line, format!("Invalid site {}: {}: {}", what, site_name, value),
+    );
+
+    let result_type = result_type.parse().ok()
+        .and_then(|code| SpectreResultType::from_upstream_code(code).ok())
+        .ok_or_else(|| illegal("type", result_type))?;
+    let algorithm = algorithm.parse().ok()
+        .and_then(|version: u32| SpectreAlgorithm::try_from(version).ok())
+        .ok_or_else(|| illegal("algorithm", algorithm))?;
+    let counter = counter.parse().map_err(|_| illegal("counter", counter))?;
+
+    let mut site = SpectreMarshalledSite::new(site_name.to_string(), result_type, counter, algorithm);
+    site.uses = uses.parse().map_err(|_| illegal("uses", uses))?;
+    site.last_used = parse_date(last_used).ok_or_else(|| illegal("last used", last_used))?;
+
+    // Visible exports have every site's password; only stateful ones are kept
+    if result_type.is_stateful() && !state.is_empty() {
+        site.result_state = Some(state.to_string());
+    }
+    if !login.is_empty() {
+        site.login_type = SpectreResultType::PersonalPassword;
+        site.login_state = Some(login.to_string());
+    }
+
+    Ok(site)
+}
+
+/// Write an upstream `.mpsites` flat export, in format 1
+///
+/// Without a user key the export is protected: sites only carry the
+/// encrypted states of personal passwords and logins. With the key it is
+/// visible, and every site carries its password and login in plaintext;
+/// sites without a login of their own, nor a standard login to fall back
+/// on, keep the login column empty, as they were read. Columns are padded by bytes, as upstream's `printf` pads them.
+pub(super) fn write_flat_format(user: &SpectreMarshalledUser, user_keys: Option<&[SpectreUserKey]>) -> Result<String> {
+    let mut out = String::new();
+    let default_type = user.default_type.upstream_code()
+        .or(SPECTRE_RESULT_DEFAULT_RESULT.upstream_code())
+        .unwrap_or_default();
+
+    out.push_str("# Master Password site export\n");
+    out.push_str(match user_keys {
+        None => "#     Export of site names and stored passwords (unless device-private) encrypted with the master key.\n",
+        Some(_) => "#     Export of site names and passwords in clear-text.\n",
+    });
+    out.push_str("# \n##\n");
+    out.push_str("# Format: 1\n");
+    let _ = writeln!(out, "# Date: {}", format_date(&Utc::now()));
+    let _ = writeln!(out, "# User Name: {}", user.user_name);
+    let _ = writeln!(out, "# Full Name: {}", user.user_name);
+    out.push_str("# Avatar: 0\n");
+    let _ = writeln!(out, "# Identicon: {}", user.identicon.encode());
+    let _ = writeln!(out, "# Key ID: {}", key_id_hex(&user.key_id));
+    let _ = writeln!(out, "# Algorithm: {}", user.algorithm.version());
+    let _ = writeln!(out, "# Default Type: {}", default_type);
+    let _ = writeln!(out, "# Passwords: {}", if user_keys.is_some() { "VISIBLE" } else { "PROTECTED" });
+    out.push_str("##\n#\n");
+    let _ = writeln!(out, "#{:>19}  {:>8}  {:>8}  {:>25}\t{:>25}\tSite", "Last", "Times", "Password", "Login", "Site");
+    let _ = writeln!(out, "#{:>19}  {:>8}  {:>8}  {:>25}\t{:>25}\tpassword", "used", "used", "type", "name", "name");
+
+    for site in &user.sites {
+        let code = match site.result_type.upstream_code() {
+            Some(code) => code,
+            // Visible exports hold the result itself, as a personal password
+            None if user_keys.is_some() && !site.result_type.is_one_time_code() => {
+                SpectreResultType::PersonalPassword.upstream_code().unwrap_or_default()
+            }
+            None => return Err(SpectreError::InvalidFileFormat(format!(
+                "{} has a {} result, which flat files can't hold", site.site_name, site.result_type.short_name()
+            ))),
+        };
+
+        let (login, password) = match user_keys {
+            None => (
+                site.login_state.clone().filter(|_| site.login_type.is_stateful()).unwrap_or_default(),
+                site.result_state.clone().filter(|_| site.result_type.is_stateful()).unwrap_or_default(),
+            ),
+            Some(user_keys) => {
+                let user_key = site_user_key(&user.user_name, user_keys, user.algorithm)?;
+                let site_key = site_user_key(&user.user_name, user_keys, site.algorithm)?;
+                let login = site_login(&user_key, &site_key, user, site)?.unwrap_or_default();
+                (login, site_export_password(&site_key, site)?)
+            }
+        };
+
+        let _ = writeln!(
+            out, "{}  {:>8}  {}:{}:{}  {}\t{}\t{}",
+            format_date(&site.last_used), site.uses, code, site.algorithm.version(), site.counter,
+            pad(&login, 25), pad(&site.site_name, 25), password
+        );
+    }
+
+    Ok(out)
+}
+
+/// Right-align text to `width` bytes, like C's `%*s`
+fn pad(text: &str, width: usize) -> String {
+    format!("{}{}", " ".repeat(width.saturating_sub(text.len())), text)
+}
+
+fn marshal_error(error_type: SpectreMarshalErrorType, line: usize, message: String) -> SpectreError {
+    SpectreError::Marshal(SpectreMarshalError::new(error_type, Some(line), message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flat_fields() {
+        let mut fields = FlatFields("2017-08-07T20:33:17Z         1  17:3:1                        \t    example.com\tstate");
+        assert_eq!(fields.next(&[' ', '\t']), "2017-08-07T20:33:17Z");
+        assert_eq!(fields.next(&[' ', '\t']), "1");
+        assert_eq!(fields.next(&[' ', '\t']), "17:3:1");
+        assert_eq!(fields.next(&['\t']), "");
+        assert_eq!(fields.next(&['\t']), "example.com");
+        assert_eq!(fields.rest(), "state");
+        assert_eq!(fields.rest(), "");
+    }
+
+    /// Exports are compared without their `# Date:` line, which is the time of writing
+    fn without_date(export: &str) -> Vec<&str> {
+        export.lines().filter(|line| !line.starts_with("# Date:")).collect()
+    }
+
+    #[test]
+    fn test_write_flat_format() {
+        let redacted = include_str!("../../tests/data/redacted.mpsites");
+        let (_, user) = parse_flat_format(redacted).unwrap();
+        let written = write_flat_format(&user.unwrap(), None).unwrap();
+        assert_eq!(without_date(&written), without_date(redacted));
+
+        let unredacted = include_str!("../../tests/data/unredacted.mpsites");
+        let (_, user) = parse_flat_format(unredacted).unwrap();
+        let mut user = user.unwrap();
+        let user_key = crate::spectre_marshal_auth(&mut user, "banana colored duckling").unwrap();
+        let written = write_flat_format(&user, Some(std::slice::from_ref(&user_key))).unwrap();
+        assert_eq!(without_date(&written), without_date(unredacted));
+
+        // A site without a login of its own keeps an empty login column
+        let bank = written.lines().find(|line| line.contains("bank.example")).unwrap();
+        assert!(bank.contains(&format!("  1056:3:1  {}\t", " ".repeat(25))), "{}", bank);
+    }
+
+    #[test]
+    fn test_write_flat_format_unrepresentable() {
+        let mut user = SpectreMarshalledUser::new("user".to_string(), SpectreIdenticon::default(), [0; 32], SPECTRE_ALGORITHM_CURRENT);
+        user.add_site(SpectreMarshalledSite::new("example.com".to_string(), SpectreResultType::Mnemonic, 1, SPECTRE_ALGORITHM_CURRENT));
+        assert!(matches!(write_flat_format(&user, None), Err(SpectreError::InvalidFileFormat(_))));
+    }
+
+    #[test]
+    fn test_pad() {
+        assert_eq!(pad("site", 6), "  site");
+        assert_eq!(pad("sité", 6), " sité");
+        assert_eq!(pad("too long", 6), "too long");
+    }
+}
@@ -0,0 +1,393 @@
+use std::str::FromStr;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+use crate::algorithm::SpectreUserKey;
+use crate::error::{Result, SpectreError};
+use crate::identicon::SpectreIdenticon;
+use crate::models::*;
+use crate::policy::SpectrePasswordPolicy;
+use crate::types::*;
+use crate::words::SpectreWordsParam;
+use super::{format_date, key_id_hex, parse_date, parse_key_id, question_answer, site_export_password, site_login, site_user_key, user_login};
+
+/// The `export.format` of the JSON files this crate writes and reads
+const JSON_FORMAT: u64 = 1;
+
+/// Where this crate keeps what upstream's layout has no field for
+const EXT: &str = "_ext_spectre";
+
+/// Where upstream's Master Password apps kept a site's URL
+const EXT_MPW: &str = "_ext_mpw";
+
+/// A JSON object being read, field by field
+///
+/// Each field taken is removed, so what's left over are the fields this
+/// crate doesn't know; those are kept and written back unchanged.
+struct JsonObject {
+    path: String,
+    fields: Map<String, Value>,
+}
+
+impl JsonObject {
+    fn new(path: impl Into<String>, value: Value) -> Result<Self> {
+        let path = path.into();
+        match value {
+            Value::Object(fields) => Ok(Self { path, fields }),
+            value => Err(illegal(&path, &value)),
+        }
+    }
+
+    fn take(&mut self, key: &str) -> Option<Value> {
+        self.fields.remove(key).filter(|value| !value.is_null())
+    }
+
+    fn field(&self, key: &str) -> String {
+        match self.path.as_str() {
+            "" => key.to_string(),
+            path => format!("{}.{}", path, key),
+        }
+    }
+
+    fn required<T>(&mut self, key: &str, read: fn(&mut Self, &str) -> Result<Option<T>>) -> Result<T> {
+        read(self, key)?.ok_or_else(|| marshal_error(SpectreMarshalErrorType::ErrorMissing, format!("Missing {}", self.field(key))))
+    }
+
+    fn object(&mut self, key: &str) -> Result<Option<JsonObject>> {
+        let path = self.field(key);
+        self.take(key).map(|value| JsonObject::new(path, value)).transpose()
+    }
+
+    fn string(&mut self, key: &str) -> Result<Option<String>> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(Value::String(text)) => Ok(Some(text)),
+            Some(value) => Err(illegal(&self.field(key), &value)),
+        }
+    }
+
+    fn parsed<T>(&mut self, key: &str, parse: impl FnOnce(&str) -> Option<T>) -> Result<Option<T>> {
+        self.string(key)?
+            .map(|text| parse(&text).ok_or_else(|| illegal(&self.field(key), &Value::String(text))))
+            .transpose()
+    }
+
+    fn number(&mut self, key: &str) -> Result<Option<u32>> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(value) => value.as_u64()
+                .and_then(|number| u32::try_from(number).ok())
+                .map(Some)
+                .ok_or_else(|| illegal(&self.field(key), &value)),
+        }
+    }
+
+    fn boolean(&mut self, key: &str) -> Result<Option<bool>> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(Value::Bool(flag)) => Ok(Some(flag)),
+            Some(value) => Err(illegal(&self.field(key), &value)),
+        }
+    }
+
+    fn date(&mut self, key: &str) -> Result<Option<DateTime<Utc>>> {
+        self.parsed(key, parse_date)
+    }
+
+    fn algorithm(&mut self, key: &str) -> Result<Option<SpectreAlgorithm>> {
+        match self.number(key)? {
+            None => Ok(None),
+            Some(version) => SpectreAlgorithm::try_from(version)
+                .map(Some)
+                .map_err(|_| illegal(&self.field(key), &json!(version))),
+        }
+    }
+
+    /// A result type: this crate's own in the extension object, or an upstream code
+    fn result_type(&mut self, key: &str, ext: &mut JsonObject) -> Result<Option<SpectreResultType>> {
+        if let Some(result_type) = ext.parsed(key, |name| SpectreResultType::from_str(name).ok())? {
+            self.take(key);
+            return Ok(Some(result_type));
+        }
+        match self.number(key)? {
+            None => Ok(None),
+            Some(code) => SpectreResultType::from_upstream_code(code)
+                .map(Some)
+                .map_err(|_| illegal(&self.field(key), &json!(code))),
+        }
+    }
+
+    /// The extension object, taken out so its unknown fields can be put back later
+    fn ext(&mut self) -> Result<JsonObject> {
+        Ok(self.object(EXT)?.unwrap_or_else(|| JsonObject { path: self.field(EXT), fields: Map::new() }))
+    }
+
+    /// The fields left over, with those left in the extension object
+    fn rest(mut self, ext: JsonObject) -> Map<String, Value> {
+        if !ext.fields.is_empty() {
+            self.fields.insert(EXT.to_string(), Value::Object(ext.fields));
+        }
+        self.fields
+    }
+}
+
+/// Parse a JSON user file
+///
+/// Upstream's layout has an `export` object describing the file, the `user`,
+/// and its `sites` keyed by name. Key IDs are hex and result types upstream
+/// codes; what upstream has no field for lives in `_ext_spectre` objects.
+/// Files without an `export` object are read in the layout this crate used
+/// to write, its models as serde lays them out.
+pub(super) fn parse_json_format(contents: &str) -> Result<(SpectreMarshalledFile, Option<SpectreMarshalledUser>)> {
+    let value: Value = serde_json::from_str(contents)?;
+    if value.get("export").is_none() {
+        let user: SpectreMarshalledUser = serde_json::from_value(value)?;
+        return Ok((SpectreMarshalledFile::new(SpectreFormat::JSON, user.redacted), Some(user)));
+    }
+
+    let mut root = JsonObject::new("", value)?;
+    let mut export = root.required("export", JsonObject::object)?;
+    let format = export.required("format", JsonObject::number)?;
+    if u64::from(format) != JSON_FORMAT {
+        return Err(marshal_error(SpectreMarshalErrorType::ErrorFormat, format!("Unexpected format: {}", format)));
+    }
+    let redacted = export.boolean("redacted")?.unwrap_or(true);
+    let mut file = SpectreMarshalledFile::new(SpectreFormat::JSON, redacted);
+    if let Some(date) = export.date("date")? {
+        file.date = date;
+    }
+
+    let Some(mut json_user) = root.object("user")? else {
+        return Ok((file, None));
+    };
+    let mut ext = json_user.ext()?;
+    let user_name = json_user.required("full_name", JsonObject::string)?;
+    let key_id = json_user.required("key_id", |object, key| object.parsed(key, parse_key_id))?;
+    let identicon = json_user.parsed("identicon", SpectreIdenticon::decode)?.unwrap_or_default();
+    let algorithm = json_user.algorithm("algorithm")?.unwrap_or(SPECTRE_ALGORITHM_CURRENT);
+
+    let mut user = SpectreMarshalledUser::new(user_name, identicon, key_id, algorithm);
+    user.redacted = redacted;
+    user.plaintext_states = !redacted;
+    user.default_type = json_user.result_type("default_type", &mut ext)?.unwrap_or(SPECTRE_RESULT_DEFAULT_RESULT);
+    user.login_type = json_user.result_type("login_type", &mut ext)?.unwrap_or_default();
+    user.login_state = json_user.string("login_name")?.filter(|_| user.login_type.is_stateful());
+    user.last_used = json_user.date("last_used")?.unwrap_or(file.date);
+    if let Some(normalization) = ext.parsed("normalization", |name| SpectreNormalization::from_str(name).ok())? {
+        user.normalization = normalization;
+    }
+    user.ext = json_user.rest(ext);
+
+    if let Some(sites) = root.object("sites")? {
+        for (site_name, site) in sites.fields {
+            let site = JsonObject::new(format!("sites.{}", site_name), site)?;
+            user.add_site(parse_json_site(site_name, site, &user)?);
+        }
+    }
+
+    Ok((file, Some(user)))
+}
+
+fn parse_json_site(site_name: String, mut json_site: JsonObject, user: &SpectreMarshalledUser) -> Result<SpectreMarshalledSite> {
+    let mut ext = json_site.ext()?;
+    let result_type = json_site.result_type("type", &mut ext)?.unwrap_or(user.default_type);
+    let counter = json_site.number("counter")?.unwrap_or(SPECTRE_COUNTER_DEFAULT);
+    let algorithm = json_site.algorithm("algorithm")?.unwrap_or(user.algorithm);
+
+    let mut site = SpectreMarshalledSite::new(site_name, result_type, counter, algorithm);
+    site.result_state = json_site.string("password")?.filter(|_| result_type.is_stateful());
+    site.login_type = json_site.result_type("login_type", &mut ext)?.unwrap_or_default();
+    site.login_state = json_site.string("login_name")?.filter(|_| site.login_type.is_stateful());
+    site.uses = json_site.number("uses")?.unwrap_or_default();
+    site.last_used = json_site.date("last_used")?.unwrap_or(user.last_used);
+
+    site.result_template = ext.parsed("template", |template| SpectreTemplate::from_str(template).ok())?;
+    site.result_words = ext.parsed("words", |words| SpectreWordsParam::from_str(words).ok())?;
+    site.login_template = ext.parsed("login_template", |template| SpectreTemplate::from_str(template).ok())?;
+    site.otp_state = ext.string("otp_state")?;
+    site.url = ext.string("url")?;
+    if site.url.is_none() {
+        site.url = json_site.fields.get(EXT_MPW).and_then(|mpw| mpw.get("url")).and_then(Value::as_str).map(str::to_string);
+    }
+    site.policy = ext.parsed("policy", |rules| SpectrePasswordPolicy::from_str(rules).ok())?;
+
+    if let Some(questions) = json_site.object("questions")? {
+        for (keyword, question) in questions.fields {
+            let mut json_question = JsonObject::new(format!("{}.questions.{}", json_site.path, keyword), question)?;
+            let mut ext = json_question.ext()?;
+            let question_type = json_question.result_type("type", &mut ext)?.unwrap_or(SPECTRE_RESULT_DEFAULT_ANSWER);
+
+            let mut question = SpectreMarshalledQuestion::new(keyword, question_type);
+            question.state = json_question.string("answer")?.filter(|_| question_type.is_stateful());
+            question.text = ext.string("text")?;
+            question.template = ext.parsed("template", |template| SpectreTemplate::from_str(template).ok())?;
+            question.ext = json_question.rest(ext);
+            site.questions.push(question);
+        }
+    }
+
+    site.ext = json_site.rest(ext);
+    Ok(site)
+}
+
+/// Write a JSON user file in upstream's layout; see [`parse_json_format`]
+///
+/// Without a user key the file is redacted: passwords, login names and
+/// answers are only written as the encrypted states of personal results.
+/// With the key every site's password, login name and answers are written
+/// in plaintext, as upstream writes them. Like the flat writer, sites without
+/// a login of their own or a standard login to fall back on get no login name.
+pub(super) fn write_json_format(user: &SpectreMarshalledUser, user_keys: Option<&[SpectreUserKey]>) -> Result<String> {
+    let user_key = user_keys.map(|user_keys| site_user_key(&user.user_name, user_keys, user.algorithm)).transpose()?;
+    let mut ext = Map::new();
+    let mut json_user = Map::new();
+    json_user.insert("avatar".into(), user.ext.get("avatar").cloned().unwrap_or(json!(0)));
+    json_user.insert("full_name".into(), json!(user.user_name));
+    json_user.insert("last_used".into(), json!(format_date(&user.last_used)));
+    if user.identicon != SpectreIdenticon::default() {
+        json_user.insert("identicon".into(), json!(user.identicon.encode()));
+    }
+    json_user.insert("key_id".into(), json!(key_id_hex(&user.key_id)));
+    json_user.insert("algorithm".into(), json!(user.algorithm.version()));
+    write_type(&mut json_user, &mut ext, "default_type", user.default_type, SPECTRE_RESULT_DEFAULT_RESULT);
+    write_type(&mut json_user, &mut ext, "login_type", user.login_type, SpectreResultType::PersonalPassword);
+    let login_name = match &user_key {
+        None => user.login_state.clone().filter(|_| user.login_type.is_stateful()),
+        Some(user_key) => user_login(user_key, user)?,
+    };
+    if let Some(login_name) = login_name {
+        json_user.insert("login_name".into(), json!(login_name));
+    }
+    if user.normalization != SpectreNormalization::default() {
+        ext.insert("normalization".into(), json!(user.normalization.name()));
+    }
+
+    let sites = user.sites.iter()
+        .map(|site| Ok((site.site_name.clone(), write_json_site(user, site, user_keys)?)))
+        .collect::<Result<Map<String, Value>>>()?;
+
+    let file = json!({
+        "export": {
+            "format": JSON_FORMAT,
+            "redacted": user_keys.is_none(),
+            "date": format_date(&Utc::now()),
+        },
+        "user": with_ext(json_user, ext, &user.ext),
+        "sites": sites,
+    });
+    Ok(serde_json::to_string_pretty(&file)?)
+}
+
+fn write_json_site(user: &SpectreMarshalledUser, site: &SpectreMarshalledSite, user_keys: Option<&[SpectreUserKey]>) -> Result<Value> {
+    let key = |algorithm| user_keys.map(|user_keys| site_user_key(&user.user_name, user_keys, algorithm)).transpose();
+    let (user_key, site_key) = (key(user.algorithm)?, key(site.algorithm)?);
+    let (password, login_name) = match user_key.as_ref().zip(site_key.as_ref()) {
+        None => (
+            site.result_state.clone().filter(|_| site.result_type.is_stateful()),
+            site.login_state.clone().filter(|_| site.login_type.is_stateful()),
+        ),
+        Some((user_key, site_key)) => (
+            Some(site_export_password(site_key, site)?).filter(|password| !password.is_empty()),
+            site_login(user_key, site_key, user, site)?,
+        ),
+    };
+
+    let mut ext = Map::new();
+    let mut json_site = Map::new();
+    write_type(&mut json_site, &mut ext, "type", site.result_type, SpectreResultType::PersonalPassword);
+    json_site.insert("counter".into(), json!(site.counter));
+    json_site.insert("algorithm".into(), json!(site.algorithm.version()));
+    if let Some(password) = password {
+        json_site.insert("password".into(), json!(password));
+    }
+    write_type(&mut json_site, &mut ext, "login_type", site.login_type, SpectreResultType::PersonalPassword);
+    if let Some(login_name) = login_name {
+        json_site.insert("login_name".into(), json!(login_name));
+    }
+    json_site.insert("uses".into(), json!(site.uses));
+    json_site.insert("last_used".into(), json!(format_date(&site.last_used)));
+
+    if !site.questions.is_empty() {
+        let questions = site.questions.iter().map(|question| {
+            let answer = match &site_key {
+                None => question.state.clone().filter(|_| question.question_type.is_stateful()),
+                Some(site_key) => Some(question_answer(site_key, site, question)?).filter(|answer| !answer.is_empty()),
+            };
+
+            let mut ext = Map::new();
+            let mut json_question = Map::new();
+            write_type(&mut json_question, &mut ext, "type", question.question_type, SpectreResultType::PersonalPassword);
+            if let Some(answer) = answer {
+                json_question.insert("answer".into(), json!(answer));
+            }
+            if let Some(text) = &question.text {
+                ext.insert("text".into(), json!(text));
+            }
+            if let Some(template) = &question.template {
+                ext.insert("template".into(), json!(template.as_str()));
+            }
+            Ok((question.keyword.clone(), with_ext(json_question, ext, &question.ext)))
+        }).collect::<Result<Map<String, Value>>>()?;
+        json_site.insert("questions".into(), Value::Object(questions));
+    }
+
+    if let Some(url) = &site.url {
+        ext.insert("url".into(), json!(url));
+    }
+    if let Some(template) = &site.result_template {
+        ext.insert("template".into(), json!(template.as_str()));
+    }
+    if let Some(words) = &site.result_words {
+        ext.insert("words".into(), json!(words.to_string()));
+    }
+    if let Some(template) = &site.login_template {
+        ext.insert("login_template".into(), json!(template.as_str()));
+    }
+    if let Some(otp_state) = &site.otp_state {
+        ext.insert("otp_state".into(), json!(otp_state));
+    }
+    if let Some(policy) = &site.policy {
+        ext.insert("policy".into(), json!(policy.to_string()));
+    }
+
+    Ok(with_ext(json_site, ext, &site.ext))
+}
+
+/// Write a result type as its upstream code
+///
+/// Types upstream has no code for are written as `fallback`, with the type
+/// itself in the extension object.
+fn write_type(object: &mut Map<String, Value>, ext: &mut Map<String, Value>, key: &str, result_type: SpectreResultType, fallback: SpectreResultType) {
+    let code = result_type.upstream_code().unwrap_or_else(|| {
+        ext.insert(key.into(), json!(result_type.short_name()));
+        fallback.upstream_code().unwrap_or_default()
+    });
+    object.insert(key.into(), json!(code));
+}
+
+/// Add the fields read but not used back to an object, and the extension object
+fn with_ext(mut object: Map<String, Value>, mut ext: Map<String, Value>, rest: &Map<String, Value>) -> Value {
+    for (key, value) in rest {
+        match (key.as_str(), value) {
+            (EXT, Value::Object(fields)) => {
+                for (key, value) in fields {
+                    ext.entry(key.clone()).or_insert_with(|| value.clone());
+                }
+            }
+            _ => {
+                object.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+    }
+    if !ext.is_empty() {
+        object.insert(EXT.into(), Value::Object(ext));
+    }
+    Value::Object(object)
+}
+
+fn illegal(field: &str, value: &Value) -> SpectreError {
+    marshal_error(SpectreMarshalErrorType::ErrorIllegal, format!("Invalid {}: {}", field, value))
+}
+
+fn marshal_error(error_type: SpectreMarshalErrorType, message: String) -> SpectreError {
+    SpectreError::Marshal(SpectreMarshalError::new(error_type, None, message))
+}
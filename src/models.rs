@@ -1,6 +1,11 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::error::{Result, SpectreError};
+use crate::identicon::SpectreIdenticon;
+use crate::policy::SpectrePasswordPolicy;
 use crate::types::*;
+use crate::words::SpectreWordsParam;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SpectreMarshalledFile {
@@ -14,14 +19,32 @@ pub struct SpectreMarshalledFile {
 #[derive(Debug, Clone)]
 pub struct SpectreMarshalError {
     pub error_type: SpectreMarshalErrorType,
+    /// The line of the file the error is on (1-based), for flat files
+    pub line: Option<usize>,
     pub message: String,
 }
 
+impl SpectreMarshalError {
+    pub fn new(error_type: SpectreMarshalErrorType, line: Option<usize>, message: impl Into<String>) -> Self {
+        Self {
+            error_type,
+            line,
+            message: message.into(),
+        }
+    }
+}
+
 impl Default for SpectreMarshalError {
     fn default() -> Self {
-        Self {
-            error_type: SpectreMarshalErrorType::Success,
-            message: String::new(),
+        Self::new(SpectreMarshalErrorType::Success, None, String::new())
+    }
+}
+
+impl std::fmt::Display for SpectreMarshalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => f.write_str(&self.message),
         }
     }
 }
@@ -76,11 +99,19 @@ pub const SPECTRE_FORMAT_LAST: SpectreFormat = SpectreFormat::JSON;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SpectreMarshalledUser {
     pub user_name: String,
-    pub identicon: [u8; 4],
+    #[serde(default)]
+    pub identicon: SpectreIdenticon,
     pub key_id: [u8; 32],
     pub algorithm: SpectreAlgorithm,
+    /// Files from before normalization load as `None`, the way their keys were derived
+    #[serde(default = "legacy_normalization")]
+    pub normalization: SpectreNormalization,
     pub redacted: bool,
     
+    /// The result type for new sites
+    #[serde(default = "default_result_type")]
+    pub default_type: SpectreResultType,
+    
     #[serde(default)]
     pub login_type: SpectreResultType,
     #[serde(default)]
@@ -90,6 +121,23 @@ pub struct SpectreMarshalledUser {
     
     #[serde(default)]
     pub sites: Vec<SpectreMarshalledSite>,
+    
+    /// Fields of an upstream JSON export's user this crate doesn't use, written back as read
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub ext: Map<String, Value>,
+    
+    /// Whether the states are plaintext from an unredacted export, to be
+    /// encrypted by [`spectre_marshal_auth`](crate::spectre_marshal_auth)
+    #[serde(skip)]
+    pub(crate) plaintext_states: bool,
+}
+
+fn default_result_type() -> SpectreResultType {
+    SPECTRE_RESULT_DEFAULT_RESULT
+}
+
+fn legacy_normalization() -> SpectreNormalization {
+    SpectreNormalization::None
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -97,13 +145,27 @@ pub struct SpectreMarshalledSite {
     pub site_name: String,
     pub result_type: SpectreResultType,
     pub result_state: Option<String>,
+    /// Template for a [`SpectreResultType::Custom`] result
+    #[serde(default)]
+    pub result_template: Option<SpectreTemplate>,
+    /// Word count and separator for a word-based result, e.g. [`SpectreResultType::Mnemonic`]
+    #[serde(default)]
+    pub result_words: Option<SpectreWordsParam>,
     pub counter: SpectreCounter,
     pub algorithm: SpectreAlgorithm,
+    /// Encrypted one-time code seed, kept apart so the site keeps its password
+    #[serde(default)]
+    pub otp_state: Option<String>,
+    /// Constraints the site puts on its passwords
+    #[serde(default)]
+    pub policy: Option<SpectrePasswordPolicy>,
     
     #[serde(default)]
     pub login_type: SpectreResultType,
     #[serde(default)]
     pub login_state: Option<String>,
+    #[serde(default)]
+    pub login_template: Option<SpectreTemplate>,
     
     #[serde(default)]
     pub url: Option<String>,
@@ -113,13 +175,25 @@ pub struct SpectreMarshalledSite {
     
     #[serde(default)]
     pub questions: Vec<SpectreMarshalledQuestion>,
+    
+    /// Fields of an upstream JSON export's site this crate doesn't use, written back as read
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub ext: Map<String, Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SpectreMarshalledQuestion {
     pub keyword: String,
+    /// The question as the site asks it
+    #[serde(default)]
+    pub text: Option<String>,
     pub question_type: SpectreResultType,
     pub state: Option<String>,
+    #[serde(default)]
+    pub template: Option<SpectreTemplate>,
+    /// Fields of an upstream JSON export's question this crate doesn't use
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub ext: Map<String, Value>,
 }
 
 impl SpectreMarshalledFile {
@@ -136,7 +210,7 @@ impl SpectreMarshalledFile {
 impl SpectreMarshalledUser {
     pub fn new(
         user_name: String,
-        identicon: [u8; 4],
+        identicon: SpectreIdenticon,
         key_id: [u8; 32],
         algorithm: SpectreAlgorithm,
     ) -> Self {
@@ -145,17 +219,22 @@ impl SpectreMarshalledUser {
             identicon,
             key_id,
             algorithm,
+            normalization: SpectreNormalization::default(),
             redacted: true,
+            default_type: SPECTRE_RESULT_DEFAULT_RESULT,
             login_type: SpectreResultType::None,
             login_state: None,
             last_used: Utc::now(),
             sites: Vec::new(),
+            ext: Map::new(),
+            plaintext_states: false,
         }
     }
     
     pub fn add_site(&mut self, site: SpectreMarshalledSite) {
         // Check if site already exists
-        if let Some(existing) = self.sites.iter_mut().find(|s| s.site_name == site.site_name) {
+        let normalization = self.normalization;
+        if let Some(existing) = self.sites.iter_mut().find(|s| same_site(normalization, &s.site_name, &site.site_name)) {
             *existing = site;
         } else {
             self.sites.push(site);
@@ -163,14 +242,20 @@ impl SpectreMarshalledUser {
     }
     
     pub fn find_site(&self, site_name: &str) -> Option<&SpectreMarshalledSite> {
-        self.sites.iter().find(|s| s.site_name == site_name)
+        self.sites.iter().find(|s| same_site(self.normalization, &s.site_name, site_name))
     }
     
     pub fn find_site_mut(&mut self, site_name: &str) -> Option<&mut SpectreMarshalledSite> {
-        self.sites.iter_mut().find(|s| s.site_name == site_name)
+        let normalization = self.normalization;
+        self.sites.iter_mut().find(|s| same_site(normalization, &s.site_name, site_name))
     }
 }
 
+/// Whether two site names derive the same keys
+fn same_site(normalization: SpectreNormalization, a: &str, b: &str) -> bool {
+    normalization.apply(a) == normalization.apply(b)
+}
+
 impl SpectreMarshalledSite {
     pub fn new(
         site_name: String,
@@ -182,24 +267,45 @@ impl SpectreMarshalledSite {
             site_name,
             result_type,
             result_state: None,
+            result_template: None,
+            result_words: None,
             counter,
             algorithm,
+            otp_state: None,
+            policy: None,
             login_type: SpectreResultType::None,
             login_state: None,
+            login_template: None,
             url: None,
             uses: 0,
             last_used: Utc::now(),
             questions: Vec::new(),
+            ext: Map::new(),
         }
     }
     
-    pub fn add_question(&mut self, question: SpectreMarshalledQuestion) {
-        // Check if question already exists
-        if let Some(existing) = self.questions.iter_mut().find(|q| q.keyword == question.keyword) {
-            *existing = question;
-        } else {
+    /// Add a question, or update the one saved under its keyword
+    ///
+    /// Questions are keyed by their last word, so different questions can
+    /// share a keyword, and with it an answer. A question whose text differs
+    /// from the one saved under its keyword is refused rather than replacing it.
+    pub fn add_question(&mut self, mut question: SpectreMarshalledQuestion) -> Result<()> {
+        let Some(existing) = self.find_question_mut(&question.keyword) else {
             self.questions.push(question);
+            return Ok(());
+        };
+        
+        match (&existing.text, &question.text) {
+            (Some(saved), Some(text)) if saved != text => {
+                return Err(SpectreError::QuestionConflict(format!(
+                    "\"{}\" has the same keyword (\"{}\") as \"{}\"", text, question.keyword, saved
+                )));
+            }
+            (Some(_), None) => question.text = existing.text.take(),
+            _ => {}
         }
+        *existing = question;
+        Ok(())
     }
     
     pub fn find_question(&self, keyword: &str) -> Option<&SpectreMarshalledQuestion> {
@@ -209,14 +315,23 @@ impl SpectreMarshalledSite {
     pub fn find_question_mut(&mut self, keyword: &str) -> Option<&mut SpectreMarshalledQuestion> {
         self.questions.iter_mut().find(|q| q.keyword == keyword)
     }
+    
+    /// Forget a question; returns it if it was stored
+    pub fn remove_question(&mut self, keyword: &str) -> Option<SpectreMarshalledQuestion> {
+        let index = self.questions.iter().position(|q| q.keyword == keyword)?;
+        Some(self.questions.remove(index))
+    }
 }
 
 impl SpectreMarshalledQuestion {
     pub fn new(keyword: String, question_type: SpectreResultType) -> Self {
         Self {
             keyword,
+            text: None,
             question_type,
             state: None,
+            template: None,
+            ext: Map::new(),
         }
     }
 }
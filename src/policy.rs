@@ -0,0 +1,307 @@
+use alloc::{format, string::{String, ToString}, vec::Vec};
+use hmac::Mac;
+use serde::{Deserialize, Serialize};
+use core::fmt;
+use core::str::FromStr;
+use crate::algorithm::HmacSha256;
+use crate::error::{Result, SpectreError};
+use crate::types::char_class_for_template;
+
+/// Longest password a policy may ask for
+pub const SPECTRE_POLICY_LENGTH_MAX: usize = 64;
+
+/// How many derived candidates to try before giving up on a policy
+const SPECTRE_POLICY_ATTEMPTS: u32 = 256;
+
+const SPECTRE_POLICY_SCOPE: &[u8] = b"com.lyndir.masterpassword.policy";
+
+/// Constraints a site puts on its passwords
+///
+/// Written as comma-separated rules, e.g. `min=8,max=12,digit,no-seq,forbid=<>`:
+/// `min=N`, `max=N`, `lower`, `upper`, `digit`, `symbol`, `no-seq` and
+/// `forbid=CHARS`, which must come last since it takes the rest of the text.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpectrePasswordPolicy {
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    /// Characters the site rejects
+    pub forbidden: String,
+    /// Reject runs of three identical or consecutive characters (`777`, `123`, `cba`)
+    pub no_sequences: bool,
+}
+
+/// The character classes a policy can require
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PolicyClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    Symbol,
+}
+
+impl PolicyClass {
+    const ALL: [Self; 4] = [Self::Lowercase, Self::Uppercase, Self::Digit, Self::Symbol];
+
+    fn of(c: char) -> Option<Self> {
+        match c {
+            'a'..='z' => Some(Self::Lowercase),
+            'A'..='Z' => Some(Self::Uppercase),
+            '0'..='9' => Some(Self::Digit),
+            ' ' => None,
+            _ => Some(Self::Symbol),
+        }
+    }
+
+    /// The characters generated for this class, in template order
+    fn chars(self) -> Vec<char> {
+        match self {
+            Self::Lowercase => [char_class_for_template('v'), char_class_for_template('c')].concat(),
+            Self::Uppercase => char_class_for_template('A').to_vec(),
+            Self::Digit => char_class_for_template('n').to_vec(),
+            Self::Symbol => char_class_for_template('o').to_vec(),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Lowercase => "lower",
+            Self::Uppercase => "upper",
+            Self::Digit => "digit",
+            Self::Symbol => "symbol",
+        }
+    }
+}
+
+impl SpectrePasswordPolicy {
+    fn requires(&self, class: PolicyClass) -> bool {
+        match class {
+            PolicyClass::Lowercase => self.require_lowercase,
+            PolicyClass::Uppercase => self.require_uppercase,
+            PolicyClass::Digit => self.require_digit,
+            PolicyClass::Symbol => self.require_symbol,
+        }
+    }
+
+    fn required_classes(&self) -> Vec<PolicyClass> {
+        PolicyClass::ALL.into_iter().filter(|class| self.requires(*class)).collect()
+    }
+
+    /// A class's characters, without the forbidden ones
+    fn allowed(&self, class: PolicyClass) -> Vec<char> {
+        class.chars().into_iter().filter(|c| !self.forbidden.contains(*c)).collect()
+    }
+
+    /// Check that some password can satisfy the policy
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: String| Err(SpectreError::InvalidPolicy(message));
+
+        let min = self.min_length.unwrap_or(0);
+        let max = self.max_length.unwrap_or(SPECTRE_POLICY_LENGTH_MAX);
+        if min > max {
+            return invalid(format!("minimum length {} is above the maximum {}", min, max));
+        }
+        if max == 0 || max > SPECTRE_POLICY_LENGTH_MAX || min > SPECTRE_POLICY_LENGTH_MAX {
+            return invalid(format!("lengths must be between 1 and {}", SPECTRE_POLICY_LENGTH_MAX));
+        }
+
+        let required = self.required_classes();
+        if required.len() > max {
+            return invalid(format!("{} required classes don't fit in {} characters", required.len(), max));
+        }
+        if let Some(class) = required.iter().find(|class| self.allowed(**class).is_empty()) {
+            return invalid(format!("every {} character is forbidden", class.name()));
+        }
+
+        Ok(())
+    }
+
+    /// Whether a password complies with every rule
+    pub fn is_satisfied_by(&self, password: &str) -> bool {
+        let chars: Vec<char> = password.chars().collect();
+
+        self.min_length.is_none_or(|min| chars.len() >= min)
+            && self.max_length.is_none_or(|max| chars.len() <= max)
+            && !chars.iter().any(|c| self.forbidden.contains(*c))
+            && self.required_classes().into_iter()
+                .all(|class| chars.iter().any(|c| PolicyClass::of(*c) == Some(class)))
+            && !(self.no_sequences && has_sequence(&chars))
+    }
+
+    /// Turn a generated password into one that complies
+    ///
+    /// A password that already complies is returned as is. Otherwise
+    /// candidates are built from a stream seeded by the site key, keeping the
+    /// password's length (within the policy's bounds) and character classes
+    /// and placing every required class; the first compliant one wins.
+    pub(crate) fn apply(&self, site_key: &[u8], password: String) -> Result<String> {
+        if self.is_satisfied_by(&password) {
+            return Ok(password);
+        }
+        self.validate()?;
+
+        let length = password.chars().count()
+            .clamp(self.min_length.unwrap_or(1), self.max_length.unwrap_or(SPECTRE_POLICY_LENGTH_MAX))
+            .max(self.required_classes().len());
+
+        // Keep the classes the result type uses, and add the required ones
+        let pool: Vec<char> = PolicyClass::ALL.into_iter()
+            .filter(|class| self.requires(*class) || password.chars().any(|c| PolicyClass::of(c) == Some(*class)))
+            .flat_map(|class| self.allowed(class))
+            .collect();
+        if pool.is_empty() {
+            return Err(SpectreError::InvalidPolicy("every character is forbidden".to_string()));
+        }
+
+        for attempt in 0..SPECTRE_POLICY_ATTEMPTS {
+            let candidate = self.candidate(site_key, attempt, length, &pool);
+            if self.is_satisfied_by(&candidate) {
+                return Ok(candidate);
+            }
+        }
+
+        Err(SpectreError::InvalidPolicy("no compliant password found".to_string()))
+    }
+
+    fn candidate(&self, site_key: &[u8], attempt: u32, length: usize, pool: &[char]) -> String {
+        let required = self.required_classes();
+        let mut seed = policy_seed(site_key, attempt, required.len() + length).into_iter();
+        let mut next = |len: usize| seed.next().map_or(0, |byte| byte as usize % len);
+
+        // Reserve a distinct position for each required class
+        let mut free: Vec<usize> = (0..length).collect();
+        let mut placed: Vec<(usize, PolicyClass)> = Vec::new();
+        for class in required {
+            let position = free.remove(next(free.len()));
+            placed.push((position, class));
+        }
+
+        (0..length).map(|position| {
+            match placed.iter().find(|(p, _)| *p == position) {
+                Some((_, class)) => {
+                    let chars = self.allowed(*class);
+                    chars[next(chars.len())]
+                }
+                None => pool[next(pool.len())],
+            }
+        }).collect()
+    }
+}
+
+/// Seed bytes for a policy candidate: HMAC-SHA256 blocks keyed with the site key
+fn policy_seed(site_key: &[u8], attempt: u32, length: usize) -> Vec<u8> {
+    let mut seed = Vec::with_capacity(length + 32);
+    let mut block = 0u32;
+    while seed.len() < length {
+        let mut mac = HmacSha256::new_from_slice(site_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(SPECTRE_POLICY_SCOPE);
+        mac.update(&attempt.to_be_bytes());
+        mac.update(&block.to_be_bytes());
+        seed.extend_from_slice(&mac.finalize().into_bytes());
+        block += 1;
+    }
+    seed
+}
+
+/// Whether three characters in a row repeat or count up or down
+fn has_sequence(chars: &[char]) -> bool {
+    chars.windows(3).any(|run| {
+        let [a, b, c] = [run[0] as i64, run[1] as i64, run[2] as i64];
+        let alphanumeric = run.iter().all(|c| c.is_ascii_alphanumeric());
+        (a == b && b == c) || (alphanumeric && b - a == c - b && (b - a).abs() == 1)
+    })
+}
+
+impl FromStr for SpectrePasswordPolicy {
+    type Err = SpectreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut policy = Self::default();
+        let invalid = || SpectreError::InvalidPolicy(format!("can't parse \"{}\"", s));
+
+        let mut rest = s;
+        while !rest.is_empty() {
+            if let Some(forbidden) = rest.strip_prefix("forbid=") {
+                policy.forbidden = forbidden.to_string();
+                break;
+            }
+            let (rule, tail) = rest.split_once(',').unwrap_or((rest, ""));
+            rest = tail;
+
+            match rule.split_once('=') {
+                Some(("min", n)) => policy.min_length = Some(n.parse().map_err(|_| invalid())?),
+                Some(("max", n)) => policy.max_length = Some(n.parse().map_err(|_| invalid())?),
+                Some(_) => return Err(invalid()),
+                None => match rule {
+                    "lower" => policy.require_lowercase = true,
+                    "upper" => policy.require_uppercase = true,
+                    "digit" => policy.require_digit = true,
+                    "symbol" => policy.require_symbol = true,
+                    "no-seq" => policy.no_sequences = true,
+                    "" => {}
+                    _ => return Err(invalid()),
+                },
+            }
+        }
+
+        policy.validate()?;
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for SpectrePasswordPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rules: Vec<String> = Vec::new();
+        if let Some(min) = self.min_length {
+            rules.push(format!("min={}", min));
+        }
+        if let Some(max) = self.max_length {
+            rules.push(format!("max={}", max));
+        }
+        for class in self.required_classes() {
+            rules.push(class.name().to_string());
+        }
+        if self.no_sequences {
+            rules.push("no-seq".to_string());
+        }
+        if !self.forbidden.is_empty() {
+            rules.push(format!("forbid={}", self.forbidden));
+        }
+
+        f.write_str(&rules.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_parsing() {
+        let policy: SpectrePasswordPolicy = "min=8,max=12,digit,no-seq,forbid=<>,".parse().unwrap();
+        assert_eq!(policy.min_length, Some(8));
+        assert_eq!(policy.max_length, Some(12));
+        assert!(policy.require_digit && policy.no_sequences);
+        assert_eq!(policy.forbidden, "<>,");
+        assert_eq!(policy.to_string(), "min=8,max=12,digit,no-seq,forbid=<>,");
+        assert_eq!(policy.to_string().parse::<SpectrePasswordPolicy>().unwrap(), policy);
+
+        assert!("min=12,max=8".parse::<SpectrePasswordPolicy>().is_err());
+        assert!("digit,forbid=0123456789".parse::<SpectrePasswordPolicy>().is_err());
+        assert!("bogus".parse::<SpectrePasswordPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_sequences() {
+        assert!(has_sequence(&"ab777".chars().collect::<Vec<_>>()));
+        assert!(has_sequence(&"x123".chars().collect::<Vec<_>>()));
+        assert!(has_sequence(&"Zcba".chars().collect::<Vec<_>>()));
+        assert!(!has_sequence(&"7662".chars().collect::<Vec<_>>()));
+        assert!(!has_sequence(&"135".chars().collect::<Vec<_>>()));
+    }
+}
@@ -0,0 +1,38 @@
+use alloc::string::String;
+
+/// The keyword that identifies a security question, as upstream picks it
+///
+/// Answers are derived with the keyword as the recovery context, so the same
+/// question gets the same answer on every site that asks it. Like upstream,
+/// the keyword is the question's last word, in lower case and without a
+/// possessive: "What was your first pet's name?" gives `name`.
+pub fn spectre_question_keyword(question: &str) -> String {
+    let is_apostrophe = |c: char| c == '\'' || c == '\u{2019}';
+
+    let word = question
+        .split(|c: char| !c.is_alphanumeric() && !is_apostrophe(c))
+        .map(|word| word.trim_matches(is_apostrophe))
+        .rfind(|word| !word.is_empty())
+        .unwrap_or_default();
+    let word = word
+        .strip_suffix("'s")
+        .or_else(|| word.strip_suffix("\u{2019}s"))
+        .unwrap_or(word);
+
+    word.chars().filter(|c| !is_apostrophe(*c)).flat_map(char::to_lowercase).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_question_keyword() {
+        assert_eq!(spectre_question_keyword("What was your first pet's name?"), "name");
+        assert_eq!(spectre_question_keyword("In what city were you born?"), "born");
+        assert_eq!(spectre_question_keyword("What is your father’s middle name"), "name");
+        assert_eq!(spectre_question_keyword("Name of your best friend's"), "friend");
+        assert_eq!(spectre_question_keyword("  Street  "), "street");
+        assert_eq!(spectre_question_keyword("?"), "");
+    }
+}
@@ -0,0 +1,132 @@
+use alloc::{string::{String, ToString}, vec::Vec};
+use core::fmt;
+use core::ops::Deref;
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+
+/// A user secret that is wiped from memory when dropped
+///
+/// `Debug` never prints the secret and comparisons run in constant time.
+/// Dereferences to `&str`, so it can be passed wherever the API takes a secret.
+#[derive(Clone, Default)]
+pub struct SpectreSecret(Zeroizing<String>);
+
+impl SpectreSecret {
+    pub fn new(secret: String) -> Self {
+        Self(Zeroizing::new(secret))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SpectreSecret {
+    fn from(secret: String) -> Self {
+        Self::new(secret)
+    }
+}
+
+impl From<&str> for SpectreSecret {
+    fn from(secret: &str) -> Self {
+        Self::new(secret.to_string())
+    }
+}
+
+impl Deref for SpectreSecret {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for SpectreSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SpectreSecret(<redacted>)")
+    }
+}
+
+impl PartialEq for SpectreSecret {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_bytes().ct_eq(other.0.as_bytes()).into()
+    }
+}
+
+impl Eq for SpectreSecret {}
+
+/// Secret key material (user keys, site keys) that is wiped from memory when dropped
+///
+/// `Debug` prints only the length and comparisons run in constant time.
+#[derive(Clone, Default)]
+pub struct SpectreSecretBytes(Zeroizing<Vec<u8>>);
+
+impl SpectreSecretBytes {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self(Zeroizing::new(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn as_mut_bytes(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+impl From<Vec<u8>> for SpectreSecretBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::new(bytes)
+    }
+}
+
+impl Deref for SpectreSecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for SpectreSecretBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for SpectreSecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SpectreSecretBytes(<{} bytes redacted>)", self.0.len())
+    }
+}
+
+impl PartialEq for SpectreSecretBytes {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_slice().ct_eq(other.0.as_slice()).into()
+    }
+}
+
+impl Eq for SpectreSecretBytes {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_is_redacted() {
+        let secret = SpectreSecret::from("banana colored duckling");
+        assert_eq!(format!("{:?}", secret), "SpectreSecret(<redacted>)");
+        assert_eq!(&*secret, "banana colored duckling");
+
+        let key = SpectreSecretBytes::new(vec![0x42; 64]);
+        assert_eq!(format!("{:?}", key), "SpectreSecretBytes(<64 bytes redacted>)");
+    }
+
+    #[test]
+    fn test_equality() {
+        assert_eq!(SpectreSecret::from("a"), SpectreSecret::from("a"));
+        assert_ne!(SpectreSecret::from("a"), SpectreSecret::from("b"));
+        assert_ne!(SpectreSecretBytes::new(vec![1, 2]), SpectreSecretBytes::new(vec![1, 2, 3]));
+    }
+}
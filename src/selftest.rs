@@ -0,0 +1,156 @@
+use alloc::{string::String, vec::Vec};
+use crate::algorithm::{spectre_user_key, spectre_site_result, SpectreUserKey};
+use crate::error::Result;
+use crate::types::*;
+use SpectreAlgorithm::{V0, V1, V2, V3};
+use SpectreKeyPurpose::{Identification, Recovery};
+use SpectreResultType::{
+    BasicPassword, LongPassword, MaximumSecurityPassword, MediumPassword, Name, Phrase, ShortPassword, PIN,
+};
+
+/// A known-answer vector from the upstream Spectre test suite
+#[derive(Debug, Clone, Copy)]
+pub struct SpectreTestVector {
+    pub id: &'static str,
+    pub algorithm: SpectreAlgorithm,
+    pub user_name: &'static str,
+    pub user_secret: &'static str,
+    pub site_name: &'static str,
+    pub result_type: SpectreResultType,
+    pub key_counter: SpectreCounter,
+    pub key_purpose: SpectreKeyPurpose,
+    pub key_context: Option<&'static str>,
+    pub result: &'static str,
+}
+
+/// The outcome of running one [`SpectreTestVector`]
+#[derive(Debug)]
+pub struct SpectreTestOutcome {
+    pub vector: &'static SpectreTestVector,
+    pub result: Result<String>,
+}
+
+impl SpectreTestOutcome {
+    pub fn passed(&self) -> bool {
+        matches!(&self.result, Ok(result) if result == self.vector.result)
+    }
+}
+
+const NAME: &str = "Robert Lee Mitchell";
+const SECRET: &str = "banana colored duckling";
+const SITE: &str = "masterpasswordapp.com";
+
+const fn vector(
+    id: &'static str,
+    algorithm: SpectreAlgorithm,
+    result_type: SpectreResultType,
+    result: &'static str,
+) -> SpectreTestVector {
+    SpectreTestVector {
+        id,
+        algorithm,
+        user_name: NAME,
+        user_secret: SECRET,
+        site_name: SITE,
+        result_type,
+        key_counter: SPECTRE_COUNTER_DEFAULT,
+        key_purpose: SpectreKeyPurpose::Authentication,
+        key_context: None,
+        result,
+    }
+}
+
+const fn with_user(mut v: SpectreTestVector, user_name: &'static str, user_secret: &'static str) -> SpectreTestVector {
+    v.user_name = user_name;
+    v.user_secret = user_secret;
+    v
+}
+
+const fn with_site(mut v: SpectreTestVector, site_name: &'static str) -> SpectreTestVector {
+    v.site_name = site_name;
+    v
+}
+
+const fn with_counter(mut v: SpectreTestVector, key_counter: SpectreCounter) -> SpectreTestVector {
+    v.key_counter = key_counter;
+    v
+}
+
+const fn with_purpose(
+    mut v: SpectreTestVector,
+    key_purpose: SpectreKeyPurpose,
+    key_context: Option<&'static str>,
+) -> SpectreTestVector {
+    v.key_purpose = key_purpose;
+    v.key_context = key_context;
+    v
+}
+
+/// Known-answer vectors covering every template type, purpose and algorithm version
+pub const SPECTRE_TEST_VECTORS: &[SpectreTestVector] = &[
+    vector("v3", V3, LongPassword, "Jejr5[RepuSosp"),
+    with_user(vector("v3_mb_userName", V3, LongPassword, "NopaDajh8=Fene"), "⛄", SECRET),
+    with_user(vector("v3_mb_userSecret", V3, LongPassword, "QesuHirv5-Xepl"), NAME, "⛄"),
+    with_site(vector("v3_mb_siteName", V3, LongPassword, "LiheCuwhSerz6)"), "⛄"),
+    with_purpose(vector("v3_loginName", V3, Name, "wohzaqage"), Identification, None),
+    with_purpose(vector("v3_securityAnswer", V3, Phrase, "xin diyjiqoja hubu"), Recovery, None),
+    with_purpose(vector("v3_securityAnswer_context", V3, Phrase, "xogx tem cegyiva jab"), Recovery, Some("question")),
+    vector("v3_type_maximum", V3, MaximumSecurityPassword, "W6@692^B1#&@gVdSdLZ@"),
+    vector("v3_type_medium", V3, MediumPassword, "Jej2$Quv"),
+    vector("v3_type_basic", V3, BasicPassword, "WAo2xIg6"),
+    vector("v3_type_short", V3, ShortPassword, "Jej2"),
+    vector("v3_type_pin", V3, PIN, "7662"),
+    vector("v3_type_name", V3, Name, "jejraquvo"),
+    vector("v3_type_phrase", V3, Phrase, "jejr quv cabsibu tam"),
+    with_counter(vector("v3_counter_ceiling", V3, LongPassword, "XambHoqo6[Peni"), u32::MAX),
+    vector("v2", V2, LongPassword, "Jejr5[RepuSosp"),
+    with_user(vector("v2_mb_userName", V2, LongPassword, "WaqoGuho2[Xaxw"), "⛄", SECRET),
+    with_site(vector("v2_mb_siteName", V2, LongPassword, "LiheCuwhSerz6)"), "⛄"),
+    vector("v1", V1, LongPassword, "Jejr5[RepuSosp"),
+    with_user(vector("v1_mb_userName", V1, LongPassword, "WaqoGuho2[Xaxw"), "⛄", SECRET),
+    with_site(vector("v1_mb_siteName", V1, LongPassword, "WawiYarp2@Kodh"), "⛄"),
+    vector("v0", V0, LongPassword, "Feji5@ReduWosh"),
+    with_user(vector("v0_mb_userName", V0, LongPassword, "HajrYudo7@Mamh"), "⛄", SECRET),
+    with_site(vector("v0_mb_siteName", V0, LongPassword, "HahiVana2@Nole"), "⛄"),
+    with_purpose(vector("v0_loginName", V0, Name, "lozwajave"), Identification, None),
+    with_purpose(vector("v0_securityAnswer", V0, Phrase, "miy lirfijoja dubu"), Recovery, None),
+    with_purpose(vector("v0_securityAnswer_context", V0, Phrase, "movm bex gevrica jaf"), Recovery, Some("question")),
+    vector("v0_type_maximum", V0, MaximumSecurityPassword, "w1!3bA3icmRAc)SS@lwl"),
+    vector("v0_type_medium", V0, MediumPassword, "Fej7]Jug"),
+    vector("v0_type_basic", V0, BasicPassword, "wvH7irC1"),
+    vector("v0_type_short", V0, ShortPassword, "Fej7"),
+    vector("v0_type_pin", V0, PIN, "2117"),
+    vector("v0_type_name", V0, Name, "fejrajugo"),
+    vector("v0_type_phrase", V0, Phrase, "fejr jug gabsibu bax"),
+    with_counter(vector("v0_counter_ceiling", V0, LongPassword, "QateDojh1@Hecn"), u32::MAX),
+];
+
+/// Run every known-answer vector, deriving each distinct user key only once
+pub fn spectre_selftest() -> Vec<SpectreTestOutcome> {
+    let mut user_keys: Vec<(&SpectreTestVector, SpectreUserKey)> = Vec::new();
+
+    SPECTRE_TEST_VECTORS.iter().map(|vector| {
+        let cached = user_keys.iter().find(|(v, _)| {
+            v.user_name == vector.user_name
+                && v.user_secret == vector.user_secret
+                && v.algorithm == vector.algorithm
+        });
+        let user_key = match cached {
+            Some((_, user_key)) => Ok(user_key.clone()),
+            None => spectre_user_key(vector.user_name, vector.user_secret, vector.algorithm)
+                .inspect(|user_key| user_keys.push((vector, user_key.clone()))),
+        };
+
+        let result = user_key.and_then(|user_key| spectre_site_result(
+            &user_key,
+            vector.site_name,
+            vector.result_type,
+            None,
+            vector.key_counter,
+            vector.key_purpose,
+            vector.key_context,
+        ));
+
+        SpectreTestOutcome { vector, result }
+    }).collect()
+}
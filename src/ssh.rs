@@ -0,0 +1,69 @@
+use hmac::Mac;
+use rand::rngs::OsRng;
+use ssh_key::private::{Ed25519Keypair, KeypairData};
+use ssh_key::{HashAlg, LineEnding, PrivateKey};
+use zeroize::Zeroizing;
+use crate::algorithm::{spectre_site_key, spectre_user_key_mac, HmacSha256, SpectreUserKey};
+use crate::batch::SpectreSiteRequest;
+use crate::error::{Result, SpectreError};
+
+const SPECTRE_SSH_SCOPE: &[u8] = b"com.lyndir.masterpassword.ssh-ed25519";
+
+/// An ed25519 SSH key pair derived for a site
+///
+/// The same user, site, counter, purpose and context always give the same
+/// key, so a lost key can be derived again instead of re-provisioned.
+pub struct SpectreSshKey(PrivateKey);
+
+impl SpectreSshKey {
+    /// The public key as an `authorized_keys` line (the `.pub` file)
+    pub fn public_key(&self) -> String {
+        self.0.public_key().to_openssh().expect("ed25519 public keys encode")
+    }
+
+    /// The private key in OpenSSH format, encrypted when a passphrase is given
+    ///
+    /// Without a passphrase the output is the same on every run; encryption
+    /// uses a random salt, as `ssh-keygen` does.
+    pub fn private_key(&self, passphrase: Option<&str>) -> Result<Zeroizing<String>> {
+        let ssh_error = |e: ssh_key::Error| SpectreError::SshKey(e.to_string());
+        match passphrase {
+            Some(passphrase) => self.0.encrypt(&mut OsRng, passphrase).map_err(ssh_error)?,
+            None => self.0.clone(),
+        }
+        .to_openssh(LineEnding::LF)
+        .map_err(ssh_error)
+    }
+
+    /// The SHA-256 fingerprint, as `ssh-keygen -l` shows it (`SHA256:...`)
+    pub fn fingerprint(&self) -> String {
+        self.0.fingerprint(HashAlg::Sha256).to_string()
+    }
+}
+
+/// Derive the SSH key for a site
+///
+/// The ed25519 seed is HMAC-SHA256 of a fixed scope keyed with the site key
+/// for the request's site, counter, purpose and context, so the seed can't be
+/// recovered from the site's passwords or derived keys. The request's result
+/// type and parameter are ignored. `comment` ends up in the `.pub` line.
+pub fn spectre_site_ssh_key(user_key: &SpectreUserKey, request: &SpectreSiteRequest, comment: &str) -> Result<SpectreSshKey> {
+    let site_key = spectre_site_key(
+        user_key.algorithm,
+        user_key.normalization,
+        &spectre_user_key_mac(user_key),
+        request.site_name,
+        request.key_counter,
+        request.key_purpose,
+        request.key_context,
+    );
+
+    let mut mac = HmacSha256::new_from_slice(&site_key)
+        .expect("HMAC accepts keys of any length");
+    mac.update(SPECTRE_SSH_SCOPE);
+    let seed = Zeroizing::new(<[u8; 32]>::from(mac.finalize().into_bytes()));
+
+    let keypair = KeypairData::Ed25519(Ed25519Keypair::from_seed(&seed));
+    let private_key = PrivateKey::new(keypair, comment).map_err(|e| SpectreError::SshKey(e.to_string()))?;
+    Ok(SpectreSshKey(private_key))
+}
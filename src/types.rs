@@ -1,19 +1,139 @@
+use alloc::{borrow::Cow, format, string::{String, ToString}};
 use serde::{Deserialize, Serialize};
-use std::str::FromStr;
+use core::str::FromStr;
+use unicode_normalization::{is_nfc_quick, is_nfkc_quick, IsNormalized, UnicodeNormalization};
 use crate::error::{Result, SpectreError};
+use crate::words::SpectreWordList;
 
-pub const SPECTRE_ALGORITHM_FIRST: u32 = 0;
-pub const SPECTRE_ALGORITHM_CURRENT: u32 = 3;
-pub const SPECTRE_ALGORITHM_LAST: u32 = 3;
+pub const SPECTRE_ALGORITHM_FIRST: SpectreAlgorithm = SpectreAlgorithm::V0;
+pub const SPECTRE_ALGORITHM_CURRENT: SpectreAlgorithm = SpectreAlgorithm::V3;
+pub const SPECTRE_ALGORITHM_LAST: SpectreAlgorithm = SpectreAlgorithm::V3;
 
 pub const SPECTRE_COUNTER_DEFAULT: u32 = 1;
 pub const SPECTRE_COUNTER_INITIAL: u32 = 0;
 pub const SPECTRE_COUNTER_FIRST: u32 = 0;
 pub const SPECTRE_COUNTER_LAST: u32 = u32::MAX;
 
-pub type SpectreAlgorithm = u32;
 pub type SpectreCounter = u32;
 
+/// Algorithm versions, each fixing a quirk of the one before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u32", into = "u32")]
+#[repr(u32)]
+pub enum SpectreAlgorithm {
+    /// Original; reads template seed bytes as sign-extended 16-bit integers
+    V0 = 0,
+    /// Fixes the template seed; still counts characters in name lengths
+    V1 = 1,
+    /// Uses the byte length of site names and key contexts
+    V2 = 2,
+    /// Uses the byte length of user names
+    V3 = 3,
+}
+
+impl SpectreAlgorithm {
+    pub const ALL: [Self; 4] = [Self::V0, Self::V1, Self::V2, Self::V3];
+
+    pub fn version(&self) -> u32 {
+        *self as u32
+    }
+
+    /// Whether the user name length in the user key salt is a byte count (v3+)
+    pub fn user_name_byte_length(&self) -> bool {
+        *self >= Self::V3
+    }
+
+    /// Whether site name and context lengths in the site key salt are byte counts (v2+)
+    pub fn site_name_byte_length(&self) -> bool {
+        *self >= Self::V2
+    }
+
+    /// Whether template seeds are read as sign-extended, byte-swapped 16-bit integers (v0)
+    pub fn signed_template_seed(&self) -> bool {
+        *self == Self::V0
+    }
+}
+
+impl Default for SpectreAlgorithm {
+    fn default() -> Self {
+        SPECTRE_ALGORITHM_CURRENT
+    }
+}
+
+impl TryFrom<u32> for SpectreAlgorithm {
+    type Error = SpectreError;
+
+    fn try_from(version: u32) -> Result<Self> {
+        match version {
+            0 => Ok(Self::V0),
+            1 => Ok(Self::V1),
+            2 => Ok(Self::V2),
+            3 => Ok(Self::V3),
+            _ => Err(SpectreError::InvalidAlgorithm(version)),
+        }
+    }
+}
+
+impl From<SpectreAlgorithm> for u32 {
+    fn from(algorithm: SpectreAlgorithm) -> Self {
+        algorithm.version()
+    }
+}
+
+impl core::fmt::Display for SpectreAlgorithm {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "v{}", self.version())
+    }
+}
+
+/// How user names, secrets, site names and contexts are normalized before they're hashed
+///
+/// The same text can be typed as different code points (a composed `é` or an
+/// `e` followed by a combining accent), which would otherwise derive different
+/// keys. NFC, the default, matches upstream; NFKC also folds compatibility forms
+/// such as full-width letters. `None` hashes the UTF-8 bytes as given, for users
+/// whose keys were derived before normalization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpectreNormalization {
+    #[default]
+    Nfc,
+    Nfkc,
+    None,
+}
+
+impl SpectreNormalization {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Nfc => "nfc",
+            Self::Nfkc => "nfkc",
+            Self::None => "none",
+        }
+    }
+
+    /// Normalize text, borrowing it when it's already in normal form
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self {
+            Self::Nfc if is_nfc_quick(text.chars()) != IsNormalized::Yes => Cow::Owned(text.nfc().collect()),
+            Self::Nfkc if is_nfkc_quick(text.chars()) != IsNormalized::Yes => Cow::Owned(text.nfkc().collect()),
+            _ => Cow::Borrowed(text),
+        }
+    }
+}
+
+impl FromStr for SpectreNormalization {
+    type Err = SpectreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "nfc" => Ok(Self::Nfc),
+            "nfkc" => Ok(Self::Nfkc),
+            "none" => Ok(Self::None),
+            _ => Err(SpectreError::InvalidNormalization(s.to_string())),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SpectreKeyPurpose {
     Authentication,
@@ -44,6 +164,19 @@ impl FromStr for SpectreKeyPurpose {
     }
 }
 
+impl TryFrom<u32> for SpectreKeyPurpose {
+    type Error = SpectreError;
+
+    fn try_from(purpose: u32) -> Result<Self> {
+        match purpose {
+            0 => Ok(Self::Authentication),
+            1 => Ok(Self::Identification),
+            2 => Ok(Self::Recovery),
+            _ => Err(SpectreError::InvalidKeyPurpose(purpose.to_string())),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
 #[repr(u32)]
 pub enum SpectreResultType {
@@ -56,16 +189,79 @@ pub enum SpectreResultType {
     PIN = 0x00010005,
     Name = 0x00010006,
     Phrase = 0x00010007,
+    Custom = 0x00010008,
     
     // Stateful results
     PersonalPassword = 0x00020000,
     DeriveKey = 0x00020001,
+    OneTimeCode = 0x00020002,
+    
+    // Word list results
+    Mnemonic = 0x00030000,
     
     #[default]
     None = 0x0,
 }
 
+impl TryFrom<u32> for SpectreResultType {
+    type Error = SpectreError;
+
+    fn try_from(code: u32) -> Result<Self> {
+        use SpectreResultType::*;
+
+        [
+            MaximumSecurityPassword, LongPassword, MediumPassword, BasicPassword, ShortPassword,
+            PIN, Name, Phrase, Custom, PersonalPassword, DeriveKey, OneTimeCode, Mnemonic, None,
+        ]
+            .into_iter()
+            .find(|result_type| *result_type as u32 == code)
+            .ok_or_else(|| SpectreError::InvalidResultType(format!("{:#010x}", code)))
+    }
+}
+
 impl SpectreResultType {
+    /// The type's code in upstream user files (`.mpsites` and `.mpjson`)
+    ///
+    /// Upstream numbers types by class and feature flags (`17` is a long
+    /// password, `1056` a personal one). Types upstream doesn't have return `None`.
+    pub fn upstream_code(&self) -> Option<u32> {
+        match self {
+            Self::MaximumSecurityPassword => Some(16),
+            Self::LongPassword => Some(17),
+            Self::MediumPassword => Some(18),
+            Self::ShortPassword => Some(19),
+            Self::BasicPassword => Some(20),
+            Self::PIN => Some(21),
+            Self::Name => Some(30),
+            Self::Phrase => Some(31),
+            Self::PersonalPassword => Some(1056),
+            Self::DeriveKey => Some(4160),
+            Self::None => Some(0),
+            Self::Custom | Self::OneTimeCode | Self::Mnemonic => None,
+        }
+    }
+
+    /// The type for an upstream code; see [`SpectreResultType::upstream_code`]
+    ///
+    /// Upstream's device-private passwords (`2081`) never leave the device
+    /// they were saved on, so they're read as personal passwords.
+    pub fn from_upstream_code(code: u32) -> Result<Self> {
+        match code {
+            16 => Ok(Self::MaximumSecurityPassword),
+            17 => Ok(Self::LongPassword),
+            18 => Ok(Self::MediumPassword),
+            19 => Ok(Self::ShortPassword),
+            20 => Ok(Self::BasicPassword),
+            21 => Ok(Self::PIN),
+            30 => Ok(Self::Name),
+            31 => Ok(Self::Phrase),
+            1056 | 2081 => Ok(Self::PersonalPassword),
+            4160 => Ok(Self::DeriveKey),
+            0 => Ok(Self::None),
+            _ => Err(SpectreError::InvalidResultType(code.to_string())),
+        }
+    }
+
     pub fn short_name(&self) -> &'static str {
         match self {
             Self::MaximumSecurityPassword => "maximum",
@@ -76,14 +272,53 @@ impl SpectreResultType {
             Self::PIN => "pin",
             Self::Name => "name",
             Self::Phrase => "phrase",
+            Self::Custom => "custom",
             Self::DeriveKey => "key",
             Self::PersonalPassword => "personal",
+            Self::OneTimeCode => "totp",
+            Self::Mnemonic => "mnemonic",
             Self::None => "none",
         }
     }
 
     pub fn is_stateful(&self) -> bool {
-        matches!(self, Self::PersonalPassword | Self::DeriveKey)
+        matches!(self, Self::PersonalPassword | Self::OneTimeCode)
+    }
+
+    /// Whether the state is a [`SpectreOtp`](crate::SpectreOtp) seed that yields one-time codes
+    pub fn is_one_time_code(&self) -> bool {
+        matches!(self, Self::OneTimeCode)
+    }
+
+    pub fn is_derived(&self) -> bool {
+        matches!(self, Self::DeriveKey)
+    }
+
+    /// The word list for word-based results, which pick words rather than fill a template
+    pub fn word_list(&self) -> Option<SpectreWordList> {
+        match self {
+            Self::Mnemonic => Some(SpectreWordList::Bip39English),
+            _ => None,
+        }
+    }
+
+    /// Whether the template comes from the caller (as a [`SpectreTemplate`] param)
+    pub fn is_custom(&self) -> bool {
+        matches!(self, Self::Custom)
+    }
+
+    /// Bits of entropy in this type's results, for the types with built-in templates
+    ///
+    /// Word-based types give the entropy of the default word count.
+    /// Custom types have [`SpectreTemplate::entropy`]; stateful and derived
+    /// types don't come from templates and return `None`.
+    #[cfg(feature = "std")]
+    pub fn entropy(&self) -> Option<f64> {
+        if let Some(list) = self.word_list() {
+            return Some(list.entropy(crate::words::SPECTRE_WORDS_COUNT_DEFAULT));
+        }
+        let templates = self.template();
+        (!templates.is_empty()).then(|| spectre_templates_entropy(templates))
     }
 
     pub fn template(&self) -> &'static [&'static str] {
@@ -156,14 +391,151 @@ impl FromStr for SpectreResultType {
             "i" | "pin" => Ok(Self::PIN),
             "n" | "name" => Ok(Self::Name),
             "p" | "phrase" => Ok(Self::Phrase),
+            "custom" => Ok(Self::Custom),
             "K" | "key" => Ok(Self::DeriveKey),
             "P" | "personal" => Ok(Self::PersonalPassword),
+            "otp" | "totp" | "hotp" => Ok(Self::OneTimeCode),
+            "w" | "words" | "mnemonic" | "bip39" => Ok(Self::Mnemonic),
             _ => Err(SpectreError::InvalidResultType(s.to_string())),
         }
     }
 }
 
 pub const SPECTRE_RESULT_DEFAULT_RESULT: SpectreResultType = SpectreResultType::LongPassword;
+pub const SPECTRE_RESULT_DEFAULT_LOGIN: SpectreResultType = SpectreResultType::Name;
+pub const SPECTRE_RESULT_DEFAULT_ANSWER: SpectreResultType = SpectreResultType::Phrase;
+
+pub const SPECTRE_DERIVE_KEY_BITS_MIN: usize = 128;
+pub const SPECTRE_DERIVE_KEY_BITS_DEFAULT: usize = 512;
+pub const SPECTRE_DERIVE_KEY_BITS_MAX: usize = 512;
+
+/// Text encoding for derived key material
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpectreKeyEncoding {
+    #[default]
+    Base64,
+    Hex,
+}
+
+impl FromStr for SpectreKeyEncoding {
+    type Err = SpectreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "b" | "b64" | "base64" => Ok(Self::Base64),
+            "h" | "hex" => Ok(Self::Hex),
+            _ => Err(SpectreError::InvalidResultParam(s.to_string())),
+        }
+    }
+}
+
+/// Parameters for [`SpectreResultType::DeriveKey`], written as `bits[:encoding]`
+/// (e.g. `256`, `128:hex`). Sizes are multiples of 8 from 128 to 512 bits;
+/// without a size, or with `0`, a 512-bit key is derived, as upstream does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpectreDeriveKeyParam {
+    pub bits: usize,
+    pub encoding: SpectreKeyEncoding,
+}
+
+impl Default for SpectreDeriveKeyParam {
+    fn default() -> Self {
+        Self {
+            bits: SPECTRE_DERIVE_KEY_BITS_DEFAULT,
+            encoding: SpectreKeyEncoding::default(),
+        }
+    }
+}
+
+impl FromStr for SpectreDeriveKeyParam {
+    type Err = SpectreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (bits, encoding) = match s.split_once(':') {
+            Some((bits, encoding)) => (bits, encoding.parse()?),
+            None => (s, SpectreKeyEncoding::default()),
+        };
+        let bits = match bits {
+            "" | "0" => SPECTRE_DERIVE_KEY_BITS_DEFAULT,
+            bits => bits.parse()
+                .map_err(|_| SpectreError::InvalidResultParam(s.to_string()))?,
+        };
+        if !(SPECTRE_DERIVE_KEY_BITS_MIN..=SPECTRE_DERIVE_KEY_BITS_MAX).contains(&bits) || bits % 8 != 0 {
+            return Err(SpectreError::InvalidResultParam(s.to_string()));
+        }
+
+        Ok(Self { bits, encoding })
+    }
+}
+
+/// Template characters that stand for a character class
+pub const SPECTRE_TEMPLATE_CLASSES: &str = "VCvcAanox ";
+
+/// Longest custom template: one site key byte per character, after the
+/// byte that upstream spends on choosing a template
+pub const SPECTRE_TEMPLATE_LENGTH_MAX: usize = 31;
+
+/// A validated template for [`SpectreResultType::Custom`], e.g. `Cvcvnnoo`
+///
+/// Every character must be one of [`SPECTRE_TEMPLATE_CLASSES`], so the
+/// template can't smuggle literal characters into the result.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SpectreTemplate(String);
+
+impl SpectreTemplate {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    #[cfg(feature = "std")]
+    pub fn entropy(&self) -> f64 {
+        spectre_templates_entropy(&[self.as_str()])
+    }
+}
+
+impl FromStr for SpectreTemplate {
+    type Err = SpectreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.is_empty() {
+            return Err(SpectreError::InvalidTemplate("template is empty".to_string()));
+        }
+        if let Some((position, c)) = s.chars().enumerate().find(|(_, c)| !SPECTRE_TEMPLATE_CLASSES.contains(*c)) {
+            return Err(SpectreError::InvalidTemplate(format!(
+                "'{}' at position {} of \"{}\" is not a character class (expected one of V, C, v, c, A, a, n, o, x or space)",
+                c, position + 1, s
+            )));
+        }
+        if s.len() > SPECTRE_TEMPLATE_LENGTH_MAX {
+            return Err(SpectreError::InvalidTemplate(format!(
+                "\"{}\" is longer than {} characters", s, SPECTRE_TEMPLATE_LENGTH_MAX
+            )));
+        }
+
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for SpectreTemplate {
+    type Error = SpectreError;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<SpectreTemplate> for String {
+    fn from(template: SpectreTemplate) -> Self {
+        template.0
+    }
+}
+
+impl core::fmt::Display for SpectreTemplate {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
 
 // Character classes for templates - based on official Spectre algorithm specification
 pub fn char_class_for_template(c: char) -> &'static [char] {
@@ -172,15 +544,71 @@ pub fn char_class_for_template(c: char) -> &'static [char] {
         'C' => &['B', 'C', 'D', 'F', 'G', 'H', 'J', 'K', 'L', 'M', 'N', 'P', 'Q', 'R', 'S', 'T', 'V', 'W', 'X', 'Y', 'Z'],
         'v' => &['a', 'e', 'i', 'o', 'u'],
         'c' => &['b', 'c', 'd', 'f', 'g', 'h', 'j', 'k', 'l', 'm', 'n', 'p', 'q', 'r', 's', 't', 'v', 'w', 'x', 'y', 'z'],
-        'A' => &['A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z'],
-        'a' => &['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z'],
+        'A' => &['A', 'E', 'I', 'O', 'U', 'B', 'C', 'D', 'F', 'G', 'H', 'J', 'K', 'L', 'M', 'N', 'P', 'Q', 'R', 'S', 'T', 'V', 'W', 'X', 'Y', 'Z'],
+        'a' => &['A', 'E', 'I', 'O', 'U', 'a', 'e', 'i', 'o', 'u', 'B', 'C', 'D', 'F', 'G', 'H', 'J', 'K', 'L', 'M', 'N', 'P', 'Q', 'R', 'S', 'T', 'V', 'W', 'X', 'Y', 'Z', 'b', 'c', 'd', 'f', 'g', 'h', 'j', 'k', 'l', 'm', 'n', 'p', 'q', 'r', 's', 't', 'v', 'w', 'x', 'y', 'z'],
         'n' => &['0', '1', '2', '3', '4', '5', '6', '7', '8', '9'],
         // Official Spectre symbol set - order matters!
         // From the reference implementation: "@&%?,=[]_:-+*$#!'^~;()/."
         'o' => &['@', '&', '%', '?', ',', '=', '[', ']', '_', ':', '-', '+', '*', '$', '#', '!', '\'', '^', '~', ';', '(', ')', '/', '.'],
-        'x' => &['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z'],
+        'x' => &['A', 'E', 'I', 'O', 'U', 'a', 'e', 'i', 'o', 'u', 'B', 'C', 'D', 'F', 'G', 'H', 'J', 'K', 'L', 'M', 'N', 'P', 'Q', 'R', 'S', 'T', 'V', 'W', 'X', 'Y', 'Z', 'b', 'c', 'd', 'f', 'g', 'h', 'j', 'k', 'l', 'm', 'n', 'p', 'q', 'r', 's', 't', 'v', 'w', 'x', 'y', 'z', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', '!', '@', '#', '$', '%', '^', '&', '*', '(', ')'],
         ' ' => &[' '],
         _ => &[],
     }
 }
 
+/// Below this many bits of entropy, front-ends warn about a result type
+pub const SPECTRE_ENTROPY_WARNING_BITS: f64 = 40.0;
+
+/// Bits of entropy in a result generated from one of `templates`
+///
+/// This is log2 of the search space an attacker who knows the result type
+/// has to cover: the sum over templates of the product of each character
+/// class's size. Literal characters and single-character classes add nothing.
+/// Needs `std` for floating-point logarithms.
+#[cfg(feature = "std")]
+pub fn spectre_templates_entropy(templates: &[&str]) -> f64 {
+    let bits: Vec<f64> = templates.iter()
+        .map(|template| template.chars()
+            .map(|c| char_class_for_template(c).len())
+            .filter(|size| *size > 1)
+            .map(|size| (size as f64).log2())
+            .sum())
+        .collect();
+
+    // log2 of the sum of 2^bits, scaled to stay within f64 range
+    let max = bits.iter().copied().fold(0.0, f64::max);
+    max + bits.iter().map(|b| (b - max).exp2()).sum::<f64>().log2()
+}
+
+/// A rough rating of how well a result resists guessing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SpectreStrength {
+    /// Only safe where guesses are rate-limited (PINs, short codes)
+    Weak,
+    /// Resists online guessing, not an offline attack on a leaked hash
+    Fair,
+    /// Resists offline attacks on a fast hash
+    Strong,
+    /// Beyond any foreseeable brute force
+    VeryStrong,
+}
+
+impl SpectreStrength {
+    pub fn from_entropy(bits: f64) -> Self {
+        match bits {
+            bits if bits < 30.0 => Self::Weak,
+            bits if bits < 50.0 => Self::Fair,
+            bits if bits < 80.0 => Self::Strong,
+            _ => Self::VeryStrong,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Weak => "weak",
+            Self::Fair => "fair",
+            Self::Strong => "strong",
+            Self::VeryStrong => "very strong",
+        }
+    }
+}
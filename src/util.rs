@@ -1,17 +1,16 @@
+use alloc::string::String;
+
 /// Parse boolean from string
 pub fn parse_bool(s: &str) -> bool {
     matches!(s, "1" | "true" | "yes" | "y" | "on")
 }
 
 /// Zero out a string's memory (security measure)
+///
+/// Prefer holding secrets in a [`SpectreSecret`](crate::SpectreSecret), which does this on drop.
 pub fn zero_string(s: &mut String) {
-    unsafe {
-        let bytes = s.as_bytes_mut();
-        for byte in bytes.iter_mut() {
-            *byte = 0;
-        }
-    }
-    s.clear();
+    use zeroize::Zeroize;
+    s.zeroize();
 }
 
 // CLI-only functions (not available in WASM)
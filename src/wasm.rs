@@ -0,0 +1,277 @@
+//! JavaScript bindings through wasm-bindgen
+//!
+//! Functions keep their upstream names (`spectre_user_key`,
+//! `spectre_site_result`, ...) and return classes with typed getters rather
+//! than JSON. Result types and key purposes are given by name, as on the
+//! command line (`"long"`, `"pin"`, `"recovery"`). Errors are thrown as
+//! JavaScript `Error`s carrying the [`SpectreError`](crate::SpectreError) message.
+
+use wasm_bindgen::prelude::*;
+use crate::algorithm::{spectre_site_result, spectre_user_key_normalized, SpectreUserKey};
+use crate::identicon::{spectre_identicon_normalized, SpectreIdenticon};
+use crate::marshal::{site_password, spectre_marshal_auth, spectre_marshal_parse};
+use crate::models::{SpectreMarshalledSite, SpectreMarshalledUser};
+use crate::secret::SpectreSecretBytes;
+use crate::types::*;
+
+/// A derived user key
+#[wasm_bindgen(js_name = SpectreUserKey)]
+pub struct WasmUserKey(SpectreUserKey);
+
+#[wasm_bindgen(js_class = SpectreUserKey)]
+impl WasmUserKey {
+    /// Rebuild a key from its `keyData`, e.g. after a worker derived it
+    #[wasm_bindgen(js_name = fromKeyData)]
+    pub fn from_key_data(key_data: Vec<u8>, algorithm: u32, normalization: Option<String>) -> Result<WasmUserKey, JsError> {
+        let normalization = normalization.as_deref().map(str::parse).transpose()?.unwrap_or_default();
+        Ok(Self(SpectreUserKey::from_key_data(SpectreSecretBytes::new(key_data), algorithm.try_into()?, normalization)))
+    }
+
+    /// SHA-256 of the key, as stored in user files
+    #[wasm_bindgen(getter, js_name = keyId)]
+    pub fn key_id(&self) -> Vec<u8> {
+        self.0.key_id.to_vec()
+    }
+
+    /// The key itself; only hand it to code that derives site results
+    #[wasm_bindgen(getter, js_name = keyData)]
+    pub fn key_data(&self) -> Vec<u8> {
+        self.0.key_data.to_vec()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn algorithm(&self) -> u32 {
+        self.0.algorithm.version()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn normalization(&self) -> String {
+        self.0.normalization.name().to_string()
+    }
+}
+
+/// A user's identicon
+#[wasm_bindgen(js_name = SpectreIdenticon)]
+pub struct WasmIdenticon(SpectreIdenticon);
+
+#[wasm_bindgen(js_class = SpectreIdenticon)]
+impl WasmIdenticon {
+    #[wasm_bindgen(getter, js_name = leftArm)]
+    pub fn left_arm(&self) -> String {
+        self.0.left_arm.to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn body(&self) -> String {
+        self.0.body.to_string()
+    }
+
+    #[wasm_bindgen(getter, js_name = rightArm)]
+    pub fn right_arm(&self) -> String {
+        self.0.right_arm.to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn accessory(&self) -> String {
+        self.0.accessory.to_string()
+    }
+
+    /// The color as a CSS value
+    #[wasm_bindgen(getter)]
+    pub fn color(&self) -> String {
+        self.0.color.css().to_string()
+    }
+
+    /// The four glyphs without any coloring
+    #[wasm_bindgen(getter)]
+    pub fn text(&self) -> String {
+        self.0.render_text()
+    }
+
+    /// Upstream's marshalled form, e.g. `2:╚☻╯⛄`
+    #[wasm_bindgen(getter)]
+    pub fn encoded(&self) -> String {
+        self.0.encode()
+    }
+}
+
+/// A site saved in a user file
+#[wasm_bindgen(js_name = SpectreMarshalledSite)]
+pub struct WasmMarshalledSite(SpectreMarshalledSite);
+
+#[wasm_bindgen(js_class = SpectreMarshalledSite)]
+impl WasmMarshalledSite {
+    #[wasm_bindgen(getter, js_name = siteName)]
+    pub fn site_name(&self) -> String {
+        self.0.site_name.clone()
+    }
+
+    /// The result type's short name, e.g. `"long"`
+    #[wasm_bindgen(getter, js_name = resultType)]
+    pub fn result_type(&self) -> String {
+        self.0.result_type.short_name().to_string()
+    }
+
+    /// The encrypted state for stateful result types
+    #[wasm_bindgen(getter, js_name = resultState)]
+    pub fn result_state(&self) -> Option<String> {
+        self.0.result_state.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn counter(&self) -> u32 {
+        self.0.counter
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn algorithm(&self) -> u32 {
+        self.0.algorithm.version()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn url(&self) -> Option<String> {
+        self.0.url.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn uses(&self) -> u32 {
+        self.0.uses
+    }
+
+    /// When the site was last used, as an RFC 3339 timestamp
+    #[wasm_bindgen(getter, js_name = lastUsed)]
+    pub fn last_used(&self) -> String {
+        self.0.last_used.to_rfc3339()
+    }
+
+    /// This site's result for a user key, with its template, word count and policy
+    ///
+    /// Empty for a personal password that was never set.
+    pub fn result(&self, user_key: &WasmUserKey) -> Result<String, JsError> {
+        Ok(site_password(&user_key.0, &self.0)?)
+    }
+}
+
+/// A user file, as parsed by [`spectre_marshal_parse`](crate::spectre_marshal_parse)
+#[wasm_bindgen(js_name = SpectreMarshalledUser)]
+pub struct WasmMarshalledUser(SpectreMarshalledUser);
+
+#[wasm_bindgen(js_class = SpectreMarshalledUser)]
+impl WasmMarshalledUser {
+    #[wasm_bindgen(getter, js_name = userName)]
+    pub fn user_name(&self) -> String {
+        self.0.user_name.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn identicon(&self) -> WasmIdenticon {
+        WasmIdenticon(self.0.identicon)
+    }
+
+    #[wasm_bindgen(getter, js_name = keyId)]
+    pub fn key_id(&self) -> Vec<u8> {
+        self.0.key_id.to_vec()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn algorithm(&self) -> u32 {
+        self.0.algorithm.version()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn redacted(&self) -> bool {
+        self.0.redacted
+    }
+
+    /// When the user last logged in, as an RFC 3339 timestamp
+    #[wasm_bindgen(getter, js_name = lastUsed)]
+    pub fn last_used(&self) -> String {
+        self.0.last_used.to_rfc3339()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn sites(&self) -> Vec<WasmMarshalledSite> {
+        self.0.sites.iter().cloned().map(WasmMarshalledSite).collect()
+    }
+
+    /// Check the user's secret and derive their key (slow: runs scrypt)
+    ///
+    /// Throws if the secret doesn't match the file.
+    pub fn authenticate(&mut self, user_secret: &str) -> Result<WasmUserKey, JsError> {
+        Ok(WasmUserKey(spectre_marshal_auth(&mut self.0, user_secret)?))
+    }
+}
+
+/// Derive the user key from a user's name and secret (slow: runs scrypt)
+///
+/// The algorithm defaults to the current version and the normalization to NFC.
+#[wasm_bindgen(js_name = spectre_user_key)]
+pub fn wasm_user_key(
+    user_name: &str,
+    user_secret: &str,
+    algorithm: Option<u32>,
+    normalization: Option<String>,
+) -> Result<WasmUserKey, JsError> {
+    let algorithm = algorithm.map(SpectreAlgorithm::try_from).transpose()?.unwrap_or_default();
+    let normalization = normalization.as_deref().map(str::parse).transpose()?.unwrap_or_default();
+
+    Ok(WasmUserKey(spectre_user_key_normalized(user_name, user_secret, algorithm, normalization)?))
+}
+
+/// Generate a site result: a password, login name or security answer
+///
+/// Defaults to a long password for authentication at the default counter.
+/// `resultParam` is the stored state for stateful types, the template or
+/// key size for custom and derived types, and the word count (and separator)
+/// for word-based types.
+#[wasm_bindgen(js_name = spectre_site_result)]
+pub fn wasm_site_result(
+    user_key: &WasmUserKey,
+    site_name: &str,
+    result_type: Option<String>,
+    result_param: Option<String>,
+    key_counter: Option<u32>,
+    key_purpose: Option<String>,
+    key_context: Option<String>,
+) -> Result<String, JsError> {
+    let key_purpose = key_purpose.as_deref().map(str::parse).transpose()?.unwrap_or(SpectreKeyPurpose::Authentication);
+    let result_type = match result_type.as_deref() {
+        Some(result_type) => result_type.parse()?,
+        None => SPECTRE_RESULT_DEFAULT_RESULT,
+    };
+
+    Ok(spectre_site_result(
+        &user_key.0,
+        site_name,
+        result_type,
+        result_param.as_deref(),
+        key_counter.unwrap_or(SPECTRE_COUNTER_DEFAULT),
+        key_purpose,
+        key_context.as_deref(),
+    )?)
+}
+
+/// The identicon for a user's name and secret (fast enough to run while typing)
+///
+/// The normalization defaults to NFC; pass the user's own to match their key.
+#[wasm_bindgen(js_name = spectre_identicon)]
+pub fn wasm_identicon(
+    user_name: &str,
+    user_secret: &str,
+    normalization: Option<String>,
+) -> Result<WasmIdenticon, JsError> {
+    let normalization = normalization.as_deref().map(str::parse).transpose()?.unwrap_or_default();
+
+    Ok(WasmIdenticon(spectre_identicon_normalized(user_name, user_secret, normalization)))
+}
+
+/// Parse the contents of a user file
+///
+/// Throws if the contents aren't a user file.
+#[wasm_bindgen(js_name = spectre_marshal_parse)]
+pub fn wasm_marshal_parse(contents: &str) -> Result<WasmMarshalledUser, JsError> {
+    match spectre_marshal_parse(contents)? {
+        (_, Some(user)) => Ok(WasmMarshalledUser(user)),
+        (_, None) => Err(JsError::new("no user in file")),
+    }
+}
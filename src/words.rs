@@ -0,0 +1,167 @@
+use alloc::{string::{String, ToString}, vec::Vec};
+use core::str::FromStr;
+use hmac::Mac;
+use serde::{Deserialize, Serialize};
+use crate::algorithm::HmacSha256;
+use crate::error::{Result, SpectreError};
+
+pub const SPECTRE_WORDS_COUNT_MIN: usize = 3;
+pub const SPECTRE_WORDS_COUNT_DEFAULT: usize = 6;
+pub const SPECTRE_WORDS_COUNT_MAX: usize = 24;
+pub const SPECTRE_WORDS_SEPARATOR_DEFAULT: &str = " ";
+
+const SPECTRE_WORDS_SCOPE: &[u8] = b"com.lyndir.masterpassword.words";
+
+/// The BIP39 English word list, as published with the BIP
+const BIP39_ENGLISH: &str = include_str!("../data/bip39_english.txt");
+
+/// Embedded word lists that word-based results pick from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpectreWordList {
+    /// 2048 words whose first four letters are unique, 11 bits each
+    Bip39English,
+}
+
+impl SpectreWordList {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Bip39English => "bip39",
+        }
+    }
+
+    pub fn words(&self) -> Vec<&'static str> {
+        match self {
+            Self::Bip39English => BIP39_ENGLISH.lines().collect(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Bip39English => 2048,
+        }
+    }
+
+    /// Bits of entropy in a phrase of `count` words from this list
+    #[cfg(feature = "std")]
+    pub fn entropy(&self, count: usize) -> f64 {
+        count as f64 * (self.size() as f64).log2()
+    }
+}
+
+/// Parameters for word-based results, written as `count[:separator]`
+/// (e.g. `8`, `6:-`, `5:`). Without a parameter six words are joined by spaces.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SpectreWordsParam {
+    pub count: usize,
+    pub separator: String,
+}
+
+impl Default for SpectreWordsParam {
+    fn default() -> Self {
+        Self {
+            count: SPECTRE_WORDS_COUNT_DEFAULT,
+            separator: SPECTRE_WORDS_SEPARATOR_DEFAULT.to_string(),
+        }
+    }
+}
+
+impl FromStr for SpectreWordsParam {
+    type Err = SpectreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (count, separator) = match s.split_once(':') {
+            Some((count, separator)) => (count, separator),
+            None => (s, SPECTRE_WORDS_SEPARATOR_DEFAULT),
+        };
+        let count = match count {
+            "" => SPECTRE_WORDS_COUNT_DEFAULT,
+            count => count.parse()
+                .map_err(|_| SpectreError::InvalidResultParam(s.to_string()))?,
+        };
+        if !(SPECTRE_WORDS_COUNT_MIN..=SPECTRE_WORDS_COUNT_MAX).contains(&count) {
+            return Err(SpectreError::InvalidResultParam(s.to_string()));
+        }
+
+        Ok(Self { count, separator: separator.to_string() })
+    }
+}
+
+impl TryFrom<String> for SpectreWordsParam {
+    type Error = SpectreError;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<SpectreWordsParam> for String {
+    fn from(param: SpectreWordsParam) -> Self {
+        param.to_string()
+    }
+}
+
+impl core::fmt::Display for SpectreWordsParam {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.separator.as_str() {
+            SPECTRE_WORDS_SEPARATOR_DEFAULT => write!(f, "{}", self.count),
+            separator => write!(f, "{}:{}", self.count, separator),
+        }
+    }
+}
+
+/// Pick words from a list with a stream seeded by the site key
+///
+/// Each word takes two seed bytes as a 16-bit index; indexes at or above
+/// the largest multiple of the list's length are skipped, so every word is
+/// equally likely.
+pub(crate) fn spectre_words_result(site_key: &[u8], list: SpectreWordList, param: &SpectreWordsParam) -> String {
+    let words = list.words();
+    let limit = (u16::MAX as usize + 1) / words.len() * words.len();
+
+    let mut picked = Vec::with_capacity(param.count);
+    let mut block = 0u32;
+    while picked.len() < param.count {
+        let mut mac = HmacSha256::new_from_slice(site_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(SPECTRE_WORDS_SCOPE);
+        mac.update(&block.to_be_bytes());
+        let seed = mac.finalize().into_bytes();
+
+        picked.extend(seed.chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]) as usize)
+            .filter(|index| *index < limit)
+            .map(|index| words[index % words.len()]));
+        block += 1;
+    }
+    picked.truncate(param.count);
+
+    picked.join(&param.separator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bip39_list() {
+        let words = SpectreWordList::Bip39English.words();
+        assert_eq!(words.len(), SpectreWordList::Bip39English.size());
+        assert_eq!(words.first(), Some(&"abandon"));
+        assert_eq!(words.last(), Some(&"zoo"));
+    }
+
+    #[test]
+    fn test_words_param() {
+        assert_eq!("".parse::<SpectreWordsParam>().unwrap(), SpectreWordsParam::default());
+        let param: SpectreWordsParam = "8:-".parse().unwrap();
+        assert_eq!((param.count, param.separator.as_str()), (8, "-"));
+        assert_eq!("5:".parse::<SpectreWordsParam>().unwrap().separator, "");
+        for param in ["8", "8:-", "5:", "6:, "] {
+            assert_eq!(param.parse::<SpectreWordsParam>().unwrap().to_string(), param);
+        }
+        assert!("2".parse::<SpectreWordsParam>().is_err());
+        assert!("25".parse::<SpectreWordsParam>().is_err());
+        assert!("six".parse::<SpectreWordsParam>().is_err());
+    }
+}
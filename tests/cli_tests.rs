@@ -0,0 +1,125 @@
+//! Run the `spectre-cli` binary against a user file of its own
+//!
+//! Each test gets a home directory under the target's temporary directory,
+//! so the user files it writes don't touch the real `~/.spectre.d`.
+
+#![cfg(feature = "cli")]
+
+use spectre::*;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const USER_NAME: &str = "Robert Lee Mitchell";
+const USER_SECRET: &str = "banana colored duckling";
+
+/// A fresh home directory for the test
+fn home_dir(test: &str) -> PathBuf {
+    let home = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("cli").join(test);
+    let _ = std::fs::remove_dir_all(&home);
+    std::fs::create_dir_all(&home).unwrap();
+    home
+}
+
+/// Run the CLI for the test user and return what it printed
+fn spectre_cli(home: &Path, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_spectre-cli"))
+        .env_clear()
+        .env("HOME", home)
+        .args(["-q", "-u", USER_NAME, "-S", USER_SECRET])
+        .args(args)
+        .output()
+        .expect("failed to run spectre-cli");
+    assert!(output.status.success(), "spectre-cli {:?} failed:\n{}", args, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap().trim_end().to_string()
+}
+
+fn read_user(home: &Path) -> SpectreMarshalledUser {
+    let path = home.join(".spectre.d").join(format!("{}.{}", USER_NAME, SpectreFormat::JSON.extension()));
+    match spectre_marshal_read(&path).expect("Failed to read user file") {
+        (_, Some(user)) => user,
+        (_, None) => panic!("no user in {}", path.display()),
+    }
+}
+
+#[test]
+fn test_one_time_code_keeps_site_counter() {
+    let home = home_dir("one_time_code_keeps_site_counter");
+    let seed: SpectreOtp = "JBSWY3DPEHPK3PXP".parse().unwrap();
+    let now = || std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+
+    // A site whose password was rotated to counter 3
+    let password = spectre_cli(&home, &["-c", "3", "github.com"]);
+    assert_eq!(read_user(&home).find_site("github.com").unwrap().counter, 3);
+
+    // Saving a seed and reading codes from it leaves that counter alone
+    let before = seed.totp(now());
+    let code = spectre_cli(&home, &["-t", "totp", "-P", "JBSWY3DPEHPK3PXP", "github.com"]);
+    assert!(code == before || code == seed.totp(now()));
+    let before = seed.totp(now());
+    let code = spectre_cli(&home, &["-t", "totp", "-c", "2", "github.com"]);
+    assert!(code == before || code == seed.totp(now()));
+
+    let user = read_user(&home);
+    let site = user.find_site("github.com").unwrap();
+    assert_eq!((site.result_type, site.counter), (SpectreResultType::LongPassword, 3));
+    assert!(site.otp_state.is_some());
+    assert_eq!(spectre_cli(&home, &["-c", "3", "github.com"]), password);
+}
+
+#[test]
+fn test_mnemonic_keeps_word_count() {
+    let home = home_dir("mnemonic_keeps_word_count");
+
+    // The count and separator given once are saved with the site
+    let phrase = spectre_cli(&home, &["-t", "mnemonic", "-P", "8:-", "phone.example"]);
+    assert_eq!(phrase.split('-').count(), 8);
+    let user = read_user(&home);
+    let site = user.find_site("phone.example").unwrap();
+    assert_eq!(site.result_words.as_ref().map(ToString::to_string).as_deref(), Some("8:-"));
+
+    // and used when they're left out
+    assert_eq!(spectre_cli(&home, &["-t", "mnemonic", "phone.example"]), phrase);
+    let shorter = spectre_cli(&home, &["-t", "mnemonic", "-P", "5", "phone.example"]);
+    assert_eq!(shorter.split(' ').count(), 5);
+    assert_eq!(spectre_cli(&home, &["-t", "mnemonic", "phone.example"]), shorter);
+}
+
+#[test]
+fn test_question_keyword_conflict() {
+    let home = home_dir("question_keyword_conflict");
+    let answer = spectre_cli(&home, &["-p", "rec", "-C", "What was your first pet's name?", "bank.example"]);
+
+    // A different question with the same keyword is refused, leaving the saved one
+    let output = Command::new(env!("CARGO_BIN_EXE_spectre-cli"))
+        .env_clear()
+        .env("HOME", &home)
+        .args(["-q", "-u", USER_NAME, "-S", USER_SECRET, "-p", "rec", "-C", "Your mother\u{2019}s maiden name", "bank.example"])
+        .output()
+        .expect("failed to run spectre-cli");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("same keyword (\"name\")"));
+    assert!(output.stdout.is_empty());
+
+    let user = read_user(&home);
+    let question = user.find_site("bank.example").unwrap().find_question("name").unwrap();
+    assert_eq!(question.text.as_deref(), Some("What was your first pet's name?"));
+    assert_eq!(spectre_cli(&home, &["-p", "rec", "-C", "name", "bank.example"]), answer);
+}
+
+#[test]
+fn test_saved_user_settings() {
+    let home = home_dir("saved_user_settings");
+    let password = spectre_cli(&home, &["-a", "0", "-t", "long", "example.com"]);
+    assert_eq!(read_user(&home).algorithm, SpectreAlgorithm::V0);
+
+    // Later runs take the algorithm from the user file
+    let user_key = spectre_user_key(USER_NAME, USER_SECRET, SpectreAlgorithm::V0).unwrap();
+    let current_key = spectre_user_key(USER_NAME, USER_SECRET, SPECTRE_ALGORITHM_CURRENT).unwrap();
+    let request = SpectreSiteRequest::new("example.com", SpectreResultType::LongPassword);
+    assert_eq!(password, spectre_site_request_result(&user_key, &request).unwrap());
+    assert_ne!(password, spectre_site_request_result(&current_key, &request).unwrap());
+    assert_eq!(spectre_cli(&home, &["example.com"]), password);
+    assert_eq!(spectre_cli(&home, &["-t", "long", "other.example"]), spectre_site_request_result(
+        &user_key, &SpectreSiteRequest::new("other.example", SpectreResultType::LongPassword),
+    ).unwrap());
+}
@@ -1,10 +1,10 @@
-/// Test using CSV file with test vectors
-/// 
-/// This test reads test cases from tests/test_vectors.csv
-/// Format: username,secret,site,type,counter,algorithm,expected
-/// 
-/// If expected is empty, the test will just print the result for manual verification
-/// If expected is provided, the test will assert the result matches
+//! Test using CSV file with test vectors
+//! 
+//! This test reads test cases from tests/test_vectors.csv
+//! Format: username,secret,site,type,counter,algorithm,expected
+//! 
+//! If expected is empty, the test will just print the result for manual verification
+//! If expected is provided, the test will assert the result matches
 
 use spectre::*;
 use std::fs::File;
@@ -58,7 +58,8 @@ impl CsvTestCase {
             _ => return Err(SpectreError::InvalidResultType(self.result_type.clone())),
         };
         
-        let user_key = spectre_user_key(&self.user_name, &self.user_secret, self.algorithm)?;
+        let algorithm = SpectreAlgorithm::try_from(self.algorithm)?;
+        let user_key = spectre_user_key(&self.user_name, &self.user_secret, algorithm)?;
         
         let result = spectre_site_result(
             &user_key,
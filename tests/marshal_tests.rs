@@ -0,0 +1,520 @@
+use spectre::*;
+
+const USER_NAME: &str = "Robert Lee Mitchell";
+const USER_SECRET: &str = "banana colored duckling";
+const KEY_ID: &str = "98EEF4D1DF46D849574A82A03C3177056B15DFFCA29BB3899DE4628453675302";
+
+const REDACTED: &str = include_str!("data/redacted.mpsites");
+const UNREDACTED: &str = include_str!("data/unredacted.mpsites");
+const FORMAT0: &str = include_str!("data/format0.mpsites");
+const UPSTREAM_JSON: &str = include_str!("data/redacted.mpjson");
+const LEGACY_JSON: &str = include_str!("data/legacy.json");
+
+fn parse(contents: &str) -> (SpectreMarshalledFile, SpectreMarshalledUser) {
+    match spectre_marshal_parse(contents).expect("Failed to parse export") {
+        (file, Some(user)) => (file, user),
+        (_, None) => panic!("no user in export"),
+    }
+}
+
+fn site_result(user_key: &SpectreUserKey, site: &SpectreMarshalledSite) -> String {
+    let mut request = SpectreSiteRequest::new(&site.site_name, site.result_type).with_counter(site.counter);
+    request.result_param = site.result_state.as_deref();
+    spectre_site_request_result(user_key, &request).expect("Failed to generate result")
+}
+
+fn key_id_hex(key_id: &[u8; 32]) -> String {
+    key_id.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+/// A temporary file of the test's own, so tests running in parallel don't share one
+fn temp_path(test: &str, format: SpectreFormat) -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.{}", test, format.extension()))
+}
+
+/// Write a user to a temporary file, returning what was written and what reads back
+fn write_and_read(test: &str, format: SpectreFormat, user: &SpectreMarshalledUser) -> (String, SpectreMarshalledUser) {
+    let path = temp_path(test, format);
+    spectre_marshal_write(&path, format, user, &[]).unwrap();
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let (_, loaded) = spectre_marshal_read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    (contents, loaded.unwrap())
+}
+
+fn marshal_error(contents: &str) -> SpectreMarshalError {
+    match spectre_marshal_parse(contents) {
+        Err(SpectreError::Marshal(error)) => error,
+        other => panic!("expected a marshal error, got {:?}", other.map(|(file, _)| file.format)),
+    }
+}
+
+#[test]
+fn test_read_redacted_flat_export() {
+    let (file, mut user) = parse(REDACTED);
+    assert_eq!(file.format, SpectreFormat::Flat);
+    assert!(file.redacted);
+    assert_eq!(file.date.to_rfc3339(), "2017-08-07T20:33:23+00:00");
+
+    assert_eq!(user.user_name, USER_NAME);
+    assert_eq!(key_id_hex(&user.key_id), KEY_ID);
+    assert_eq!(user.identicon.encode(), "2:╚☻╯⛄");
+    assert_eq!(user.algorithm, SpectreAlgorithm::V3);
+    assert_eq!(user.default_type, SpectreResultType::LongPassword);
+    assert!(user.redacted);
+
+    let sites: Vec<_> = user.sites.iter()
+        .map(|site| (site.site_name.as_str(), site.result_type, site.algorithm, site.counter, site.uses))
+        .collect();
+    assert_eq!(sites, [
+        ("masterpasswordapp.com", SpectreResultType::LongPassword, SpectreAlgorithm::V3, 1, 3),
+        ("forum.example", SpectreResultType::MaximumSecurityPassword, SpectreAlgorithm::V3, 2, 1),
+        ("bank.example", SpectreResultType::PersonalPassword, SpectreAlgorithm::V3, 1, 12),
+        ("old.example", SpectreResultType::BasicPassword, SpectreAlgorithm::V1, 1, 0),
+    ]);
+    assert_eq!(user.sites[0].last_used.to_rfc3339(), "2017-08-07T20:33:17+00:00");
+
+    let user_key = spectre_marshal_auth(&mut user, USER_SECRET).unwrap();
+    assert_eq!(site_result(&user_key, &user.sites[0]), "Jejr5[RepuSosp");
+    assert_eq!(site_result(&user_key, &user.sites[2]), "correct horse battery staple");
+    assert_eq!(spectre_site_login(&user_key, &user, &user.sites[1]).unwrap(), "robert.mitchell");
+
+    // Sites keep the algorithm they were created with
+    let v1_key = spectre_user_key(USER_NAME, USER_SECRET, user.sites[3].algorithm).unwrap();
+    assert_eq!(site_result(&v1_key, &user.sites[3]), "FLI7LJH2");
+}
+
+#[test]
+fn test_read_unredacted_flat_export() {
+    let (file, mut user) = parse(UNREDACTED);
+    assert!(!file.redacted);
+    assert!(!user.redacted);
+
+    // Plaintext is only kept for stateful results, and encrypted on authentication
+    assert_eq!(user.sites[0].result_state, None);
+    assert_eq!(user.sites[1].result_state.as_deref(), Some("correct horse battery staple"));
+
+    let user_key = spectre_marshal_auth(&mut user, USER_SECRET).unwrap();
+    assert_ne!(user.sites[1].result_state.as_deref(), Some("correct horse battery staple"));
+    assert_eq!(site_result(&user_key, &user.sites[0]), "Jejr5[RepuSosp");
+    assert_eq!(site_result(&user_key, &user.sites[1]), "correct horse battery staple");
+    assert_eq!(spectre_site_login(&user_key, &user, &user.sites[0]).unwrap(), "jikdejuho");
+
+    // A second authentication leaves the encrypted states alone
+    let state = user.sites[1].result_state.clone();
+    spectre_marshal_auth(&mut user, USER_SECRET).unwrap();
+    assert_eq!(user.sites[1].result_state, state);
+}
+
+#[test]
+fn test_read_format0_flat_export() {
+    let (_, mut user) = parse(FORMAT0);
+    assert_eq!(user.algorithm, SpectreAlgorithm::V0);
+    assert_eq!(user.identicon, SpectreIdenticon::default());
+
+    let site = &user.sites[0];
+    assert_eq!((site.site_name.as_str(), site.algorithm, site.counter, site.uses), ("legacy.example", SpectreAlgorithm::V0, 1, 7));
+    assert_eq!(site.login_state, None);
+
+    let user_key = spectre_marshal_auth(&mut user, USER_SECRET).unwrap();
+    assert_eq!(site_result(&user_key, &user.sites[0]), "FayaCelyDury6@");
+}
+
+#[test]
+fn test_flat_export_through_json() {
+    let (_, user) = parse(REDACTED);
+    let path = temp_path("flat_export_through_json", SpectreFormat::JSON);
+    spectre_marshal_write(&path, SpectreFormat::JSON, &user, &[]).unwrap();
+    let (file, loaded) = spectre_marshal_read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let loaded = loaded.unwrap();
+
+    assert_eq!(file.format, SpectreFormat::JSON);
+    assert_eq!(loaded.key_id, user.key_id);
+    assert_eq!(loaded.default_type, user.default_type);
+    assert_eq!(loaded.sites.len(), user.sites.len());
+    for (loaded, site) in loaded.sites.iter().zip(&user.sites) {
+        assert_eq!(loaded.site_name, site.site_name);
+        assert_eq!(loaded.result_state, site.result_state);
+        assert_eq!(loaded.login_state, site.login_state);
+        assert_eq!(loaded.last_used, site.last_used);
+    }
+}
+
+#[test]
+fn test_flat_export_round_trip() {
+    for (name, export) in [("redacted", REDACTED), ("format0", FORMAT0)] {
+        let (_, user) = parse(export);
+        let path = temp_path(&format!("flat_export_round_trip_{}", name), SpectreFormat::Flat);
+        spectre_marshal_write(&path, SpectreFormat::Flat, &user, &[]).unwrap();
+        let (file, loaded) = spectre_marshal_read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(file.format, SpectreFormat::Flat);
+        assert!(file.redacted);
+        assert_eq!(loaded.user_name, user.user_name);
+        assert_eq!(loaded.identicon, user.identicon);
+        assert_eq!(loaded.key_id, user.key_id);
+        assert_eq!(loaded.algorithm, user.algorithm);
+        assert_eq!(loaded.default_type, user.default_type);
+        assert_eq!(loaded.sites.len(), user.sites.len());
+        for (loaded, site) in loaded.sites.iter().zip(&user.sites) {
+            assert_eq!(
+                (&loaded.site_name, loaded.result_type, loaded.algorithm, loaded.counter, loaded.uses, loaded.last_used),
+                (&site.site_name, site.result_type, site.algorithm, site.counter, site.uses, site.last_used),
+            );
+            assert_eq!(loaded.result_state, site.result_state);
+            assert_eq!(loaded.login_state, site.login_state);
+        }
+    }
+}
+
+#[test]
+fn test_read_upstream_json() {
+    let (file, mut user) = parse(UPSTREAM_JSON);
+    assert_eq!(file.format, SpectreFormat::JSON);
+    assert!(file.redacted);
+    assert_eq!(file.date.to_rfc3339(), "2017-08-07T20:33:23+00:00");
+
+    assert_eq!(user.user_name, USER_NAME);
+    assert_eq!(key_id_hex(&user.key_id), KEY_ID);
+    assert_eq!(user.identicon.encode(), "2:╚☻╯⛄");
+    assert_eq!(user.default_type, SpectreResultType::LongPassword);
+    assert_eq!(user.login_type, SpectreResultType::Name);
+
+    // Sites keep the order they're listed in
+    let sites: Vec<_> = user.sites.iter()
+        .map(|site| (site.site_name.as_str(), site.result_type, site.algorithm, site.counter, site.uses))
+        .collect();
+    assert_eq!(sites, [
+        ("masterpasswordapp.com", SpectreResultType::LongPassword, SpectreAlgorithm::V3, 1, 3),
+        ("forum.example", SpectreResultType::MaximumSecurityPassword, SpectreAlgorithm::V3, 2, 1),
+        ("bank.example", SpectreResultType::PersonalPassword, SpectreAlgorithm::V3, 1, 12),
+        ("old.example", SpectreResultType::BasicPassword, SpectreAlgorithm::V1, 1, 0),
+    ]);
+    assert_eq!(user.sites[0].url.as_deref(), Some("https://masterpasswordapp.com"));
+    let keywords: Vec<_> = user.sites[0].questions.iter().map(|question| question.keyword.as_str()).collect();
+    assert_eq!(keywords, ["", "mother"]);
+
+    let user_key = spectre_marshal_auth(&mut user, USER_SECRET).unwrap();
+    assert_eq!(site_result(&user_key, &user.sites[0]), "Jejr5[RepuSosp");
+    assert_eq!(site_result(&user_key, &user.sites[2]), "correct horse battery staple");
+    assert_eq!(spectre_site_login(&user_key, &user, &user.sites[1]).unwrap(), "robert.mitchell");
+}
+
+#[test]
+fn test_write_upstream_json() {
+    let (_, user) = parse(UPSTREAM_JSON);
+    let (contents, loaded) = write_and_read("write_upstream_json", SpectreFormat::JSON, &user);
+
+    let written: serde_json::Value = serde_json::from_str(&contents).unwrap();
+    assert_eq!(written["export"]["format"], 1);
+    assert_eq!(written["export"]["redacted"], true);
+    assert_eq!(written["user"]["key_id"], KEY_ID);
+    assert_eq!(written["user"]["default_type"], 17);
+    assert_eq!(written["sites"]["forum.example"]["type"], 16);
+    assert_eq!(written["sites"]["forum.example"]["login_name"], "xjEyTv4ag2HMeLOpZHTuCg==");
+    assert_eq!(written["sites"]["bank.example"]["password"], "rcGDSCJC0jVjnrCpUdTmuUiQrhJGllPY9qW9Y4OMtvY=");
+    assert_eq!(written["sites"]["masterpasswordapp.com"]["questions"]["mother"]["type"], 31);
+
+    // Fields this crate doesn't use are written back as read
+    assert_eq!(written["user"]["_ext_mpw"]["hidePasswords"], false);
+    assert_eq!(written["sites"]["forum.example"]["favourite"], true);
+    assert_eq!(written["sites"]["masterpasswordapp.com"]["_ext_mpw"]["url"], "https://masterpasswordapp.com");
+    assert_eq!(written["sites"]["masterpasswordapp.com"]["_ext_spectre"]["url"], "https://masterpasswordapp.com");
+
+    assert_eq!(loaded.key_id, user.key_id);
+    assert_eq!(loaded.identicon, user.identicon);
+    assert_eq!(loaded.ext, user.ext);
+    assert_eq!(loaded.sites.len(), user.sites.len());
+    for (loaded, site) in loaded.sites.iter().zip(&user.sites) {
+        assert_eq!(
+            (&loaded.site_name, loaded.result_type, loaded.algorithm, loaded.counter, loaded.uses, loaded.last_used),
+            (&site.site_name, site.result_type, site.algorithm, site.counter, site.uses, site.last_used),
+        );
+        assert_eq!((&loaded.result_state, &loaded.login_state, &loaded.url), (&site.result_state, &site.login_state, &site.url));
+        assert_eq!(loaded.questions.len(), site.questions.len());
+    }
+}
+
+#[test]
+fn test_json_extensions() {
+    let (_, mut user) = parse(UPSTREAM_JSON);
+    user.normalization = SpectreNormalization::Nfkc;
+    user.default_type = SpectreResultType::Mnemonic;
+
+    let mut site = SpectreMarshalledSite::new("custom.example".to_string(), SpectreResultType::Custom, 1, SpectreAlgorithm::V3);
+    site.result_template = Some("Cvcc nnnn".parse().unwrap());
+    site.policy = Some("min=8,digit".parse().unwrap());
+    site.otp_state = Some("c2VlZA==".to_string());
+    let mut question = SpectreMarshalledQuestion::new("pet".to_string(), SpectreResultType::Phrase);
+    question.text = Some("Name of your first pet?".to_string());
+    site.questions.push(question);
+    user.add_site(site);
+
+    let (contents, loaded) = write_and_read("json_extensions", SpectreFormat::JSON, &user);
+    let written: serde_json::Value = serde_json::from_str(&contents).unwrap();
+
+    // Types upstream has no code for are written as the nearest it has
+    assert_eq!(written["user"]["default_type"], 17);
+    assert_eq!(written["user"]["_ext_spectre"]["default_type"], "mnemonic");
+    assert_eq!(written["sites"]["custom.example"]["type"], 1056);
+    assert_eq!(written["sites"]["custom.example"]["_ext_spectre"]["type"], "custom");
+
+    assert_eq!(loaded.normalization, SpectreNormalization::Nfkc);
+    assert_eq!(loaded.default_type, SpectreResultType::Mnemonic);
+    let site = loaded.find_site("custom.example").unwrap();
+    assert_eq!(site.result_type, SpectreResultType::Custom);
+    assert_eq!(site.result_template.as_ref().map(|template| template.as_str()), Some("Cvcc nnnn"));
+    assert_eq!(site.policy, user.sites[4].policy);
+    assert_eq!(site.otp_state.as_deref(), Some("c2VlZA=="));
+    assert_eq!(site.questions[0].text.as_deref(), Some("Name of your first pet?"));
+}
+
+#[test]
+fn test_mnemonic_words_round_trip() {
+    let (_, mut user) = parse(UPSTREAM_JSON);
+    let user_key = spectre_marshal_auth(&mut user, USER_SECRET).unwrap();
+    let phrase = spectre_site_result(&user_key, "phone.example", SpectreResultType::Mnemonic, Some("8:-"), 1, SpectreKeyPurpose::Authentication, None).unwrap();
+    assert_eq!(phrase.split('-').count(), 8);
+
+    let mut site = SpectreMarshalledSite::new("phone.example".to_string(), SpectreResultType::Mnemonic, 1, SpectreAlgorithm::V3);
+    site.result_words = Some("8:-".parse().unwrap());
+    user.add_site(site);
+
+    // Saved with the site, and used for the password an unredacted export shows
+    let (contents, loaded) = write_and_read("mnemonic_words_round_trip", SpectreFormat::JSON, &user);
+    let written: serde_json::Value = serde_json::from_str(&contents).unwrap();
+    assert_eq!(written["sites"]["phone.example"]["_ext_spectre"]["words"], "8:-");
+    user.redacted = false;
+    let exported: serde_json::Value = serde_json::from_str(&spectre_marshal_export(SpectreFormat::JSON, &user, std::slice::from_ref(&user_key)).unwrap()).unwrap();
+    assert_eq!(exported["sites"]["phone.example"]["password"], phrase.as_str());
+
+    // Regenerating from the loaded site gives the same words
+    let site = loaded.find_site("phone.example").unwrap();
+    let words = site.result_words.as_ref().map(ToString::to_string);
+    assert_eq!(words.as_deref(), Some("8:-"));
+    let mut request = SpectreSiteRequest::new(&site.site_name, site.result_type).with_counter(site.counter);
+    request.result_param = words.as_deref();
+    assert_eq!(spectre_site_request_result(&user_key, &request).unwrap(), phrase);
+}
+
+#[test]
+fn test_read_legacy_json() {
+    let (file, mut user) = parse(LEGACY_JSON);
+    assert_eq!(file.format, SpectreFormat::JSON);
+    assert_eq!(key_id_hex(&user.key_id), KEY_ID);
+    assert_eq!(user.sites.len(), 4);
+
+    let user_key = spectre_marshal_auth(&mut user, USER_SECRET).unwrap();
+    assert_eq!(site_result(&user_key, &user.sites[2]), "correct horse battery staple");
+
+    // Saving moves it to upstream's layout
+    let (contents, loaded) = write_and_read("read_legacy_json", SpectreFormat::JSON, &user);
+    assert!(contents.contains(&format!("\"key_id\": \"{}\"", KEY_ID)));
+    assert_eq!(loaded.sites[2].result_state, user.sites[2].result_state);
+}
+
+#[test]
+fn test_unredacted_json_export() {
+    let (_, mut user) = parse(UPSTREAM_JSON);
+    let user_key = spectre_marshal_auth(&mut user, USER_SECRET).unwrap();
+
+    // Redacted users are written as held, key or not
+    let redacted = spectre_marshal_export(SpectreFormat::JSON, &user, std::slice::from_ref(&user_key)).unwrap();
+    assert!(redacted.contains("\"redacted\": true"));
+    assert!(!redacted.contains("Jejr5[RepuSosp"));
+    assert!(!redacted.contains("correct horse battery staple"));
+
+    // Unredacted ones must be unlocked with their own key
+    user.redacted = false;
+    assert!(matches!(spectre_marshal_export(SpectreFormat::JSON, &user, &[]), Err(SpectreError::MissingField(_))));
+    let other_key = spectre_user_key(USER_NAME, "banana colored ducklings", user.algorithm).unwrap();
+    assert!(matches!(
+        spectre_marshal_export(SpectreFormat::JSON, &user, std::slice::from_ref(&other_key)),
+        Err(SpectreError::UserSecretMismatch)
+    ));
+
+    let unredacted = spectre_marshal_export(SpectreFormat::JSON, &user, std::slice::from_ref(&user_key)).unwrap();
+    let written: serde_json::Value = serde_json::from_str(&unredacted).unwrap();
+    assert_eq!(written["export"]["redacted"], false);
+    assert_eq!(written["sites"]["masterpasswordapp.com"]["password"], "Jejr5[RepuSosp");
+    assert_eq!(written["sites"]["masterpasswordapp.com"]["login_name"], "jikdejuho");
+    assert_eq!(written["sites"]["forum.example"]["login_name"], "robert.mitchell");
+    assert_eq!(written["sites"]["bank.example"]["password"], "correct horse battery staple");
+    assert_eq!(written["sites"]["old.example"]["password"], "FLI7LJH2");
+    let answer = written["sites"]["masterpasswordapp.com"]["questions"]["mother"]["answer"].as_str().unwrap();
+    assert_eq!(answer.split(' ').count(), 4);
+
+    // Sites without a login, for a user without a standard login, don't get one made up
+    let mut plain = user.clone();
+    plain.login_type = SpectreResultType::None;
+    plain.add_site(SpectreMarshalledSite::new("nologin.example".to_string(), SpectreResultType::LongPassword, 1, SpectreAlgorithm::V3));
+    let export = spectre_marshal_export(SpectreFormat::JSON, &plain, std::slice::from_ref(&user_key)).unwrap();
+    let exported: serde_json::Value = serde_json::from_str(&export).unwrap();
+    assert!(exported["sites"]["nologin.example"].get("login_name").is_none());
+    assert_eq!(exported["sites"]["masterpasswordapp.com"]["login_name"], "jikdejuho");
+    let (_, mut reloaded) = parse(&export);
+    spectre_marshal_auth(&mut reloaded, USER_SECRET).unwrap();
+    let site = reloaded.find_site("nologin.example").unwrap();
+    assert_eq!((site.login_type, site.login_state.as_deref()), (SpectreResultType::None, None));
+    spectre_user_set_login(&user_key, &mut plain, Some("robert@example.com")).unwrap();
+    let export = spectre_marshal_export(SpectreFormat::JSON, &plain, std::slice::from_ref(&user_key)).unwrap();
+    let exported: serde_json::Value = serde_json::from_str(&export).unwrap();
+    assert_eq!(exported["sites"]["nologin.example"]["login_name"], "robert@example.com");
+
+    // Reading it back encrypts the secrets again
+    let (file, mut loaded) = parse(&unredacted);
+    assert!(!file.redacted);
+    assert_eq!(loaded.sites[2].result_state.as_deref(), Some("correct horse battery staple"));
+    let loaded_key = spectre_marshal_auth(&mut loaded, USER_SECRET).unwrap();
+    assert_ne!(loaded.sites[2].result_state.as_deref(), Some("correct horse battery staple"));
+    assert_eq!(site_result(&loaded_key, &loaded.sites[2]), "correct horse battery staple");
+    assert_eq!(spectre_site_login(&loaded_key, &loaded, &loaded.sites[1]).unwrap(), "robert.mitchell");
+    assert_eq!(site_result(&loaded_key, &loaded.sites[0]), "Jejr5[RepuSosp");
+}
+
+#[test]
+fn test_unredacted_json_states_round_trip() {
+    const SEED: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    let (_, mut user) = parse(UPSTREAM_JSON);
+    let user_key = spectre_marshal_auth(&mut user, USER_SECRET).unwrap();
+    user.redacted = false;
+    spectre_user_set_login(&user_key, &mut user, Some("robert@example.com")).unwrap();
+
+    // Each state sealed the way its reader opens it, away from the default counter
+    let mut site = SpectreMarshalledSite::new("states.example".to_string(), SpectreResultType::LongPassword, 3, SpectreAlgorithm::V3);
+    site.login_type = SpectreResultType::PersonalPassword;
+    site.login_state = Some(spectre_site_state(&user_key, &site.site_name, site.login_type, "rlm",
+        SPECTRE_COUNTER_INITIAL, SpectreKeyPurpose::Identification, None).unwrap());
+    site.otp_state = Some(spectre_site_state(&user_key, &site.site_name, SpectreResultType::OneTimeCode, SEED,
+        SPECTRE_COUNTER_DEFAULT, SpectreKeyPurpose::Authentication, None).unwrap());
+    let mut question = SpectreMarshalledQuestion::new("pet".to_string(), SpectreResultType::PersonalPassword);
+    question.state = Some(spectre_site_state(&user_key, &site.site_name, question.question_type, "Rex",
+        SPECTRE_COUNTER_INITIAL, SpectreKeyPurpose::Recovery, Some("pet")).unwrap());
+    site.add_question(question).unwrap();
+    user.add_site(site);
+    let mut codes = SpectreMarshalledSite::new("codes.example".to_string(), SpectreResultType::OneTimeCode, 2, SpectreAlgorithm::V3);
+    codes.result_state = Some(spectre_site_state(&user_key, &codes.site_name, codes.result_type, SEED,
+        codes.counter, SpectreKeyPurpose::Authentication, None).unwrap());
+    user.add_site(codes);
+
+    let export = spectre_marshal_export(SpectreFormat::JSON, &user, std::slice::from_ref(&user_key)).unwrap();
+    let (_, mut loaded) = parse(&export);
+    let loaded_key = spectre_marshal_auth(&mut loaded, USER_SECRET).unwrap();
+
+    let site = loaded.find_site("states.example").unwrap();
+    assert_eq!(spectre_site_login(&loaded_key, &loaded, site).unwrap(), "rlm");
+    let question = site.find_question("pet").unwrap();
+    let answer = SpectreSiteRequest::new(&site.site_name, question.question_type)
+        .with_counter(SPECTRE_COUNTER_INITIAL)
+        .with_purpose(SpectreKeyPurpose::Recovery, Some(&question.keyword))
+        .with_param(question.state.as_deref().unwrap());
+    assert_eq!(spectre_site_request_result(&loaded_key, &answer).unwrap(), "Rex");
+    let seed = SpectreSiteRequest::new(&site.site_name, SpectreResultType::OneTimeCode)
+        .with_param(site.otp_state.as_deref().unwrap());
+    assert_eq!(spectre_site_otp(&loaded_key, &seed).unwrap().code(59), "287082");
+
+    let codes = loaded.find_site("codes.example").unwrap();
+    assert_eq!(spectre_site_login(&loaded_key, &loaded, codes).unwrap(), "robert@example.com");
+    let seed = SpectreSiteRequest::new(&codes.site_name, codes.result_type)
+        .with_counter(codes.counter)
+        .with_param(codes.result_state.as_deref().unwrap());
+    assert_eq!(spectre_site_otp(&loaded_key, &seed).unwrap().code(59), "287082");
+}
+
+#[test]
+fn test_unredacted_export_other_algorithm_keys() {
+    // v2 counts the user name's characters and v3 its bytes, so a non-ASCII name derives different keys
+    const NAME: &str = "Zoë Mitchell";
+    let user_key = spectre_user_key(NAME, USER_SECRET, SpectreAlgorithm::V3).unwrap();
+    let old_key = spectre_user_key(NAME, USER_SECRET, SpectreAlgorithm::V2).unwrap();
+    assert!(!user_key.covers_algorithm(NAME, SpectreAlgorithm::V2));
+    assert!(user_key.covers_algorithm(USER_NAME, SpectreAlgorithm::V2));
+
+    let mut user = SpectreMarshalledUser::new(NAME.to_string(), SpectreIdenticon::default(), user_key.key_id, SpectreAlgorithm::V3);
+    user.redacted = false;
+    spectre_user_set_login(&user_key, &mut user, Some("zoe@example.com")).unwrap();
+    let mut site = SpectreMarshalledSite::new("old.example".to_string(), SpectreResultType::PersonalPassword, 1, SpectreAlgorithm::V2);
+    site.result_state = Some(spectre_site_state(&old_key, &site.site_name, site.result_type, "hunter2",
+        site.counter, SpectreKeyPurpose::Authentication, None).unwrap());
+    user.add_site(site);
+
+    assert!(matches!(
+        spectre_marshal_export(SpectreFormat::JSON, &user, std::slice::from_ref(&user_key)),
+        Err(SpectreError::MissingField(_))
+    ));
+    let export = spectre_marshal_export(SpectreFormat::JSON, &user, &[user_key, old_key.clone()]).unwrap();
+    let written: serde_json::Value = serde_json::from_str(&export).unwrap();
+    assert_eq!(written["sites"]["old.example"]["password"], "hunter2");
+    assert_eq!(written["sites"]["old.example"]["login_name"], "zoe@example.com");
+
+    // Reading it back seals the site's password with the key it's read with
+    let (_, mut loaded) = parse(&export);
+    spectre_marshal_auth(&mut loaded, USER_SECRET).unwrap();
+    assert_eq!(site_result(&old_key, &loaded.sites[0]), "hunter2");
+}
+
+#[test]
+fn test_unredacted_flat_export() {
+    let (_, mut user) = parse(UNREDACTED);
+    let user_key = spectre_marshal_auth(&mut user, USER_SECRET).unwrap();
+    assert!(!user.redacted);
+
+    let path = temp_path("unredacted_flat_export", SpectreFormat::Flat);
+    spectre_marshal_write(&path, SpectreFormat::Flat, &user, std::slice::from_ref(&user_key)).unwrap();
+    let contents = std::fs::read_to_string(&path).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+    std::fs::remove_file(&path).unwrap();
+
+    let without_date = |export: &str| export.lines().filter(|line| !line.starts_with("# Date:")).map(str::to_string).collect::<Vec<_>>();
+    assert_eq!(without_date(&contents), without_date(UNREDACTED));
+}
+
+#[test]
+fn test_json_export_errors() {
+    let error = marshal_error(&UPSTREAM_JSON.replace("\"format\": 1", "\"format\": 2"));
+    assert_eq!(error.error_type, SpectreMarshalErrorType::ErrorFormat);
+
+    let error = marshal_error(&UPSTREAM_JSON.replace(&format!("\"key_id\": \"{}\",", KEY_ID), ""));
+    assert_eq!(error.error_type, SpectreMarshalErrorType::ErrorMissing);
+    assert_eq!(error.message, "Missing user.key_id");
+
+    let error = marshal_error(&UPSTREAM_JSON.replace("\"type\": 20", "\"type\": 99"));
+    assert_eq!(error.error_type, SpectreMarshalErrorType::ErrorIllegal);
+    assert_eq!(error.message, "Invalid sites.old.example.type: 99");
+}
+
+#[test]
+fn test_flat_export_errors() {
+    let error = marshal_error(&REDACTED.replace("  17:3:1  ", "  99:3:1  "));
+    assert_eq!(error.error_type, SpectreMarshalErrorType::ErrorIllegal);
+    assert_eq!(error.line, Some(19));
+    assert_eq!(error.to_string(), "line 19: Invalid site type: masterpasswordapp.com: 99");
+
+    let error = marshal_error(&REDACTED.replace("  20:1:1  ", "  20:7:1  "));
+    assert_eq!((error.error_type, error.line), (SpectreMarshalErrorType::ErrorIllegal, Some(22)));
+
+    let error = marshal_error(&REDACTED.replace("2016-12-24T09:15:00Z", "Christmas"));
+    assert_eq!((error.error_type, error.line), (SpectreMarshalErrorType::ErrorIllegal, Some(21)));
+
+    let error = marshal_error(&REDACTED.replace(&format!("# Key ID: {}\n", KEY_ID), ""));
+    assert_eq!((error.error_type, error.line), (SpectreMarshalErrorType::ErrorMissing, Some(14)));
+    assert_eq!(error.message, "Missing header: Key ID");
+
+    let error = marshal_error(&REDACTED.replace("# Format: 1", "# Format: 2"));
+    assert_eq!((error.error_type, error.line), (SpectreMarshalErrorType::ErrorFormat, Some(5)));
+
+    let error = marshal_error(&REDACTED.replace("# Algorithm: 3", "# Algorithm: three"));
+    assert_eq!((error.error_type, error.line), (SpectreMarshalErrorType::ErrorIllegal, Some(12)));
+
+    let error = marshal_error("2017-08-07T20:33:17Z  1  17:3:1\t\tmasterpasswordapp.com\t\n");
+    assert_eq!((error.error_type, error.line), (SpectreMarshalErrorType::ErrorFormat, None));
+
+    assert!(matches!(spectre_marshal_parse("{ \"user_name\": "), Err(SpectreError::Json(_))));
+}
@@ -0,0 +1,56 @@
+//! Check that the core builds without `std`
+//!
+//! Builds the library with `--no-default-features`, which compiles it as
+//! `#![no_std]` with `alloc`, denying warnings: code only the `std` build
+//! uses shows up as unused here. When a bare-metal target is installed
+//! (`rustup target add thumbv7em-none-eabihf`), the core is also checked
+//! for it, which catches dependencies that quietly pull in `std`.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const BARE_METAL_TARGET: &str = "thumbv7em-none-eabihf";
+
+fn cargo_check(target: Option<&str>) {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+    // A target directory of its own, so this doesn't wait on the build lock
+    // held by the `cargo test` that runs it
+    let mut command = Command::new(env!("CARGO"));
+    command
+        .current_dir(manifest_dir)
+        .env("RUSTFLAGS", "-Dwarnings")
+        .args(["check", "--lib", "--no-default-features", "--quiet"])
+        .arg("--target-dir")
+        .arg(manifest_dir.join("target").join("no_std"));
+    if let Some(target) = target {
+        command.args(["--target", target]);
+    }
+
+    let output = command.output().expect("failed to run cargo");
+    assert!(
+        output.status.success(),
+        "no_std build failed{}:\n{}",
+        target.map(|t| format!(" for {}", t)).unwrap_or_default(),
+        String::from_utf8_lossy(&output.stderr),
+    );
+}
+
+fn target_installed(target: &str) -> bool {
+    let Ok(output) = Command::new("rustc").args(["--print", "sysroot"]).output() else {
+        return false;
+    };
+    let sysroot = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    sysroot.join("lib").join("rustlib").join(target).exists()
+}
+
+#[test]
+fn test_no_std_build() {
+    cargo_check(None);
+
+    if target_installed(BARE_METAL_TARGET) {
+        cargo_check(Some(BARE_METAL_TARGET));
+    } else {
+        println!("{} not installed, skipping the bare-metal check", BARE_METAL_TARGET);
+    }
+}
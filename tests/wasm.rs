@@ -0,0 +1,111 @@
+//! Tests for the JavaScript bindings, run under a headless wasm runner
+//!
+//! ```bash
+//! cargo install wasm-bindgen-cli   # provides wasm-bindgen-test-runner
+//! cargo test --target wasm32-unknown-unknown --features wasm --test wasm
+//! ```
+//!
+//! The runner executes them in Node.js; `.cargo/config.toml` points cargo at it.
+
+#![cfg(all(feature = "wasm", target_arch = "wasm32"))]
+
+use spectre::wasm::*;
+use wasm_bindgen_test::*;
+
+const USER_NAME: &str = "Robert Lee Mitchell";
+const USER_SECRET: &str = "banana colored duckling";
+const SITE_NAME: &str = "masterpasswordapp.com";
+
+fn user_key() -> WasmUserKey {
+    wasm_user_key(USER_NAME, USER_SECRET, None, None).unwrap()
+}
+
+#[wasm_bindgen_test]
+fn test_site_results() {
+    let user_key = user_key();
+    let result = |result_type: &str, purpose: Option<&str>, context: Option<&str>| {
+        wasm_site_result(
+            &user_key,
+            SITE_NAME,
+            Some(result_type.to_string()),
+            None,
+            None,
+            purpose.map(str::to_string),
+            context.map(str::to_string),
+        )
+        .unwrap()
+    };
+
+    assert_eq!(wasm_site_result(&user_key, SITE_NAME, None, None, None, None, None).unwrap(), "Jejr5[RepuSosp");
+    assert_eq!(result("pin", None, None), "7662");
+    assert_eq!(result("name", Some("ident"), None), "wohzaqage");
+    assert_eq!(result("phrase", Some("recovery"), Some("question")), "xogx tem cegyiva jab");
+    assert!(wasm_site_result(&user_key, SITE_NAME, Some("bogus".to_string()), None, None, None, None).is_err());
+}
+
+#[wasm_bindgen_test]
+fn test_user_key_round_trip() {
+    let user_key = user_key();
+    assert_eq!(user_key.algorithm(), 3);
+    assert_eq!(user_key.normalization(), "nfc");
+
+    let rebuilt = WasmUserKey::from_key_data(user_key.key_data(), user_key.algorithm(), None).unwrap();
+    assert_eq!(rebuilt.key_id(), user_key.key_id());
+    assert_eq!(wasm_site_result(&rebuilt, SITE_NAME, None, None, None, None, None).unwrap(), "Jejr5[RepuSosp");
+}
+
+#[wasm_bindgen_test]
+fn test_identicon() {
+    let identicon = wasm_identicon(USER_NAME, USER_SECRET, None).unwrap();
+    assert_eq!(identicon.encoded(), "2:╚☻╯⛄");
+    assert_eq!(identicon.text(), "╚☻╯⛄");
+    assert_eq!(identicon.left_arm(), "╚");
+    assert_eq!(identicon.accessory(), "⛄");
+
+    // Users from before normalization see the identicon of the bytes as typed
+    let (decomposed, secret_decomposed) = ("Ame\u{301}lie Franc\u{327}ois", "cafe\u{301} cre\u{300}me");
+    let raw = wasm_identicon(decomposed, secret_decomposed, Some("none".to_string())).unwrap();
+    let nfc = wasm_identicon(decomposed, secret_decomposed, None).unwrap();
+    assert_eq!(raw.encoded(), spectre::spectre_identicon_normalized(decomposed, secret_decomposed, spectre::SpectreNormalization::None).encode());
+    assert_ne!(raw.encoded(), nfc.encoded());
+    assert!(wasm_identicon(USER_NAME, USER_SECRET, Some("nfd".to_string())).is_err());
+}
+
+#[wasm_bindgen_test]
+fn test_marshal_parse() {
+    let user_key = user_key();
+    let key_id: Vec<String> = user_key.key_id().iter().map(u8::to_string).collect();
+    let contents = format!(
+        r#"{{
+            "user_name": "{}", "identicon": "2:╚☻╯⛄", "key_id": [{}], "algorithm": 3,
+            "redacted": true, "last_used": "2024-01-01T00:00:00Z",
+            "sites": [{{
+                "site_name": "{}", "result_type": "PIN", "result_state": null,
+                "counter": 1, "algorithm": 3, "uses": 2, "last_used": "2024-01-01T00:00:00Z"
+            }}, {{
+                "site_name": "{}", "result_type": "Mnemonic", "result_state": null, "result_words": "8:-",
+                "counter": 1, "algorithm": 3, "uses": 0, "last_used": "2024-01-01T00:00:00Z"
+            }}, {{
+                "site_name": "{}", "result_type": "LongPassword", "result_state": null, "policy": {{ "forbidden": "[" }},
+                "counter": 1, "algorithm": 3, "uses": 0, "last_used": "2024-01-01T00:00:00Z"
+            }}]
+        }}"#,
+        USER_NAME, key_id.join(","), SITE_NAME, SITE_NAME, SITE_NAME,
+    );
+
+    let mut user = wasm_marshal_parse(&contents).unwrap();
+    assert_eq!(user.user_name(), USER_NAME);
+    assert_eq!(user.identicon().encoded(), "2:╚☻╯⛄");
+    let sites = user.sites();
+    assert_eq!(sites.len(), 3);
+    assert_eq!(sites[0].result_type(), "pin");
+    assert_eq!(sites[0].uses(), 2);
+
+    let user_key = user.authenticate(USER_SECRET).unwrap();
+    assert_eq!(sites[0].result(&user_key).unwrap(), "7662");
+    // Results follow the site's word count and policy
+    assert_eq!(sites[1].result(&user_key).unwrap(), "scrap-amazing-curve-goat-cigar-drip-wire-language");
+    assert_eq!(sites[2].result(&user_key).unwrap(), "3u7q;BU&u]D7vJ");
+    assert!(user.authenticate("banana colored ducklings").is_err());
+    assert!(wasm_marshal_parse("not a user file").is_err());
+}